@@ -0,0 +1,173 @@
+//! Resolve CLI targets into the markdown files `runme` should scan.
+//!
+//! A target may be a plain file, a directory (walked recursively while
+//! honoring `.gitignore`), or a glob pattern such as `guides/**/*.md`.
+
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use globset::{GlobBuilder, GlobMatcher};
+use ignore::WalkBuilder;
+
+const MARKDOWN_EXTENSIONS: &[&str] = &["md", "markdown"];
+
+/// Expand targets into a de-duplicated list of markdown files in discovery order.
+pub fn discover(targets: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut seen = HashSet::new();
+    let mut files = Vec::new();
+
+    for target in targets {
+        let found = if is_glob(target) {
+            expand_glob(target)?
+        } else if target.is_dir() {
+            walk(target, is_markdown)?
+        } else {
+            // Plain files are taken verbatim so missing paths surface a read error later.
+            vec![target.clone()]
+        };
+
+        for path in found.iter().map(|path| strip_cur_dir(path)) {
+            if seen.insert(path.clone()) {
+                files.push(path);
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+fn is_glob(target: &Path) -> bool {
+    target.to_string_lossy().contains(['*', '?', '[', '{'])
+}
+
+fn is_markdown(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| MARKDOWN_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+fn expand_glob(pattern: &Path) -> Result<Vec<PathBuf>> {
+    // Walked paths lose their "./" in `glob_matches`, so the pattern has to as well.
+    let pattern = strip_cur_dir(pattern);
+    let raw = pattern.to_string_lossy();
+    let matcher = GlobBuilder::new(&raw)
+        .literal_separator(true)
+        .build()
+        .with_context(|| format!("invalid glob pattern {raw}"))?
+        .compile_matcher();
+
+    let root = glob_root(&pattern);
+    let files = walk(&root, |path| glob_matches(&matcher, path))?;
+    if files.is_empty() {
+        bail!("glob pattern {raw} matched no files");
+    }
+    Ok(files)
+}
+
+/// Longest leading run of components without glob metacharacters; the walk starts there.
+fn glob_root(pattern: &Path) -> PathBuf {
+    let mut root = PathBuf::new();
+    for component in pattern.components() {
+        if is_glob(Path::new(component.as_os_str())) {
+            break;
+        }
+        root.push(component);
+    }
+    if root.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        root
+    }
+}
+
+fn glob_matches(matcher: &GlobMatcher, path: &Path) -> bool {
+    matcher.is_match(strip_cur_dir(path))
+}
+
+/// Walking from "." yields "./docs/a.md"; drop the prefix so it matches and dedupes as "docs/a.md".
+fn strip_cur_dir(path: &Path) -> PathBuf {
+    path.components()
+        .skip_while(|component| matches!(component, Component::CurDir))
+        .collect()
+}
+
+fn walk(root: &Path, mut keep: impl FnMut(&Path) -> bool) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let walker = WalkBuilder::new(root)
+        // Honor .gitignore even when the docs tree is not inside a git checkout.
+        .require_git(false)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build();
+
+    for entry in walker {
+        let entry = entry.with_context(|| format!("while walking {}", root.display()))?;
+        let is_file = entry.file_type().is_some_and(|kind| kind.is_file());
+        if is_file && keep(entry.path()) {
+            files.push(entry.into_path());
+        }
+    }
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Build a throwaway docs tree under the system temp dir.
+    fn docs_tree(name: &str) -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("runme-discovery-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("docs/nested")).unwrap();
+        fs::create_dir_all(root.join("docs/build")).unwrap();
+        fs::write(root.join("README.md"), "# Root\n").unwrap();
+        fs::write(root.join("docs/intro.md"), "# Intro\n").unwrap();
+        fs::write(root.join("docs/nested/deep.markdown"), "# Deep\n").unwrap();
+        fs::write(root.join("docs/notes.txt"), "not markdown\n").unwrap();
+        fs::write(root.join("docs/build/generated.md"), "# Generated\n").unwrap();
+        fs::write(root.join(".gitignore"), "build/\n").unwrap();
+        root
+    }
+
+    #[test]
+    fn walks_directories_and_honors_gitignore() {
+        let root = docs_tree("dirs");
+        let files = discover(std::slice::from_ref(&root)).expect("discover directory");
+
+        assert_eq!(
+            files,
+            vec![
+                root.join("README.md"),
+                root.join("docs/intro.md"),
+                root.join("docs/nested/deep.markdown"),
+            ]
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn expands_globs_and_deduplicates_targets() {
+        let root = docs_tree("globs");
+        let pattern = root.join("docs/**/*.md");
+        let files = discover(&[root.join("docs/intro.md"), pattern]).expect("discover glob");
+
+        assert_eq!(files, vec![root.join("docs/intro.md")]);
+
+        let missing = discover(&[root.join("nowhere/*.md")]);
+        assert!(missing.is_err(), "empty globs should be reported");
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn globs_with_a_leading_cur_dir_match_like_plain_ones() {
+        // Relative to the crate root, where cargo runs tests.
+        let plain = discover(&[PathBuf::from("src/report/*.rs")]).expect("plain glob");
+        let dotted = discover(&[PathBuf::from("./src/report/*.rs")]).expect("./ glob");
+
+        assert!(plain.contains(&PathBuf::from("src/report/mod.rs")));
+        assert_eq!(dotted, plain);
+    }
+}
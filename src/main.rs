@@ -1,14 +1,32 @@
+mod discovery;
 mod markdown;
+mod report;
 mod runner;
+mod select;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
-use markdown::CodeBlock;
-use runner::{BlockReport, DockerSandbox, HostSandbox, Sandbox, WasmSandbox};
+use markdown::{CodeBlock, DocumentSettings, ExecMode, ParsedDocument, Phase};
+use report::source_ref;
+use runner::{
+    BlockReport, BlockStatus, ContainerRuntime, DockerSandbox, ExecEvents, ExecOptions,
+    HostSandbox, HumanStreamer, Sandbox, SessionPool, WasmLimits, WasmSandbox,
+};
+use select::BlockFilter;
+
+/// Exit status when at least one block failed (suppressed by `--allow-failures`).
+const EXIT_BLOCKS_FAILED: u8 = 1;
+/// Exit status when runme itself could not complete, e.g. unreadable docs or sandbox errors.
+/// Matches the code clap uses for invalid command lines.
+const EXIT_INTERNAL_ERROR: u8 = 2;
+/// Exit status when the targets or `--block` selection yielded no blocks at all.
+const EXIT_NO_BLOCKS_MATCHED: u8 = 3;
 
 /// `runme` keeps README snippets honest by parsing markdown and
 /// executing runnable blocks inside small sandboxes (shell-only for now).
@@ -19,22 +37,42 @@ use runner::{BlockReport, DockerSandbox, HostSandbox, Sandbox, WasmSandbox};
     about = "Execute README code blocks on demand"
 )]
 struct Cli {
-    /// Path to the primary README markdown file.
-    #[arg(default_value = "README.md")]
-    target: PathBuf,
-
     /// Sandbox runtime to execute code blocks with.
     #[arg(long, value_enum, default_value_t = SandboxChoice::Host)]
     sandbox: SandboxChoice,
 
+    /// Container CLI for --sandbox=docker: `docker`, `podman`, `nerdctl`, or a path to a
+    /// compatible binary. Rootless Podman runs get `--userns=keep-id` unless a --docker-arg
+    /// sets `--userns`.
+    #[arg(long, value_name = "RUNTIME", default_value = "docker", value_parser = parse_container_runtime)]
+    container_runtime: ContainerRuntime,
+
     /// Container image used when --sandbox=docker (overrides RUNME_DOCKER_IMAGE).
     #[arg(long, value_name = "IMAGE")]
     docker_image: Option<String>,
 
+    /// Build the --sandbox=docker image from this Dockerfile (context: its directory) and
+    /// cache it under a tag derived from the Dockerfile contents. Overrides `runme:dockerfile`.
+    #[arg(long, value_name = "PATH", conflicts_with = "docker_image")]
+    docker_build: Option<PathBuf>,
+
     /// Repeatable extra arguments forwarded to `docker run`.
     #[arg(long = "docker-arg", value_name = "ARG", action = ArgAction::Append)]
     docker_args: Vec<String>,
 
+    /// Map a command name to a WASI module for --sandbox=wasm, e.g. `ls=tools/ls.wasm`;
+    /// repeatable. Commands without a mapping fail with exit code 127.
+    #[arg(long = "wasm-tool", value_name = "NAME=PATH", value_parser = parse_wasm_tool, action = ArgAction::Append)]
+    wasm_tools: Vec<(String, PathBuf)>,
+
+    /// Fuel (roughly Wasm instructions) each command may consume under --sandbox=wasm.
+    #[arg(long, value_name = "UNITS", default_value_t = WasmLimits::default().fuel)]
+    wasm_fuel: u64,
+
+    /// Linear memory each command may use under --sandbox=wasm, in MiB.
+    #[arg(long, value_name = "MIB", default_value_t = WasmLimits::default().memory_bytes >> 20, value_parser = parse_mebibytes)]
+    wasm_memory: usize,
+
     #[command(flatten)]
     run: RunArgs,
 
@@ -45,32 +83,118 @@ struct Cli {
 #[derive(Subcommand, Debug)]
 enum Command {
     /// List discovered blocks with metadata but do not execute them.
-    List,
+    List(TargetArgs),
     /// Execute runnable blocks, optionally targeting a subset.
     Run(RunArgs),
+    /// Execute blocks and rewrite mismatching `runme:expect` blocks with the captured stdout.
+    Update(RunArgs),
 }
 
-#[derive(Copy, Clone, Debug, ValueEnum)]
+#[derive(Copy, Clone, Debug, PartialEq, ValueEnum)]
 enum ReportFormat {
     Human,
     Json,
+    /// JUnit XML with one testsuite per markdown file.
+    Junit,
+    /// Test Anything Protocol, streamed as each block finishes.
+    Tap,
+    /// JSON Lines progress events (run, block, line, output chunk) as they happen.
+    Jsonl,
+    /// GitHub Actions workflow commands: grouped output plus error/warning annotations.
+    Github,
+    /// Self-contained HTML page rendering each document with its block results.
+    Html,
+    /// Markdown results table with failure details, for PR comments and job summaries.
+    Markdown,
+}
+
+impl ReportFormat {
+    /// Formats rendered once at the end of the run, as opposed to printed while blocks run.
+    fn is_document(self) -> bool {
+        matches!(self, Self::Json | Self::Junit | Self::Html | Self::Markdown)
+    }
+}
+
+/// One `--report FORMAT=PATH` artifact, written alongside the `--format` output.
+#[derive(Clone, Debug, PartialEq)]
+struct ReportSpec {
+    format: ReportFormat,
+    path: PathBuf,
+}
+
+fn parse_report_spec(raw: &str) -> Result<ReportSpec, String> {
+    let (format, path) = raw
+        .split_once('=')
+        .ok_or_else(|| format!("expected FORMAT=PATH, got `{raw}`"))?;
+    let format = ReportFormat::from_str(format, true)?;
+    if matches!(format, ReportFormat::Human | ReportFormat::Github) {
+        return Err("human and github output go to the terminal; pick them with --format".into());
+    }
+    if path.is_empty() {
+        return Err(format!("missing path in `{raw}`"));
+    }
+    Ok(ReportSpec {
+        format,
+        path: PathBuf::from(path),
+    })
+}
+
+#[derive(Args, Debug, Clone, Default)]
+struct TargetArgs {
+    /// Markdown files, directories (walked recursively, honoring .gitignore) or glob
+    /// patterns to scan. Defaults to README.md.
+    #[arg(value_name = "TARGET")]
+    targets: Vec<PathBuf>,
+    #[command(flatten)]
+    filter: BlockFilter,
 }
 
 #[derive(Args, Debug, Clone)]
 struct RunArgs {
-    /// Block identifier (e.g. block-002) or custom name (`runme:name ...`) to execute.
-    #[arg(long)]
-    block: Option<String>,
+    #[command(flatten)]
+    targets: TargetArgs,
     /// Output format for reports.
     #[arg(long, default_value_t = ReportFormat::Human, value_enum)]
     format: ReportFormat,
+    /// Exit with status 0 even when blocks fail; runme errors still exit non-zero.
+    #[arg(long)]
+    allow_failures: bool,
+    /// Default execution mode: `lines` runs each line via execve, `script` feeds the whole
+    /// block to bash/sh/zsh. Blocks can override it with `runme:mode`.
+    #[arg(long, value_enum, default_value_t = ExecMode::Lines)]
+    mode: ExecMode,
+    /// Run each document's blocks in one persistent shell so `cd`/`export`/`source` carry
+    /// over; `runme:session=name` directives pick a named shell instead.
+    #[arg(long)]
+    session: bool,
+    /// Kill blocks (and every process they started) that run longer than this, e.g. `30s`,
+    /// `500ms` or `2m`. Blocks can override it with `runme:timeout`.
+    #[arg(long, value_parser = markdown::parse_duration)]
+    timeout: Option<Duration>,
+    /// Write the report to this file instead of stdout (json, junit, html and markdown).
+    #[arg(long, value_name = "PATH")]
+    output: Option<PathBuf>,
+    /// Also write a report to a file, e.g. `junit=out/junit.xml`; repeatable. Live output
+    /// still follows --format. Accepts json, junit, html, markdown, tap and jsonl.
+    #[arg(long = "report", value_name = "FORMAT=PATH", value_parser = parse_report_spec, action = ArgAction::Append)]
+    reports: Vec<ReportSpec>,
+    /// Number of slowest blocks listed in the human summary at the end of a run.
+    #[arg(long, value_name = "N", default_value_t = 5)]
+    slowest: usize,
 }
 
 impl Default for RunArgs {
     fn default() -> Self {
         Self {
-            block: None,
+            targets: TargetArgs::default(),
             format: ReportFormat::Human,
+            allow_failures: false,
+            mode: ExecMode::Lines,
+            session: false,
+            timeout: None,
+            output: None,
+            reports: Vec::new(),
+            slowest: 5,
         }
     }
 }
@@ -80,113 +204,625 @@ enum SandboxChoice {
     Host,
     Docker,
     Wasm,
+    Ns,
 }
 
 #[derive(Clone, Debug, Default)]
 struct DockerConfig {
+    runtime: ContainerRuntime,
     image: Option<String>,
+    /// Dockerfile to build the image from; takes precedence over `image`.
+    dockerfile: Option<PathBuf>,
     extra_args: Vec<String>,
 }
 
 impl DockerConfig {
     fn from_cli(cli: &Cli) -> Self {
         Self {
+            runtime: cli.container_runtime.clone(),
             image: cli.docker_image.clone(),
+            dockerfile: cli.docker_build.clone(),
             extra_args: cli.docker_args.clone(),
         }
     }
+
+    /// Configuration for one document: without an image or Dockerfile on the command line,
+    /// the document's `runme:dockerfile` (relative to the markdown file) is built.
+    fn for_document(&self, document: &Document) -> Self {
+        let mut config = self.clone();
+        if config.image.is_none() && config.dockerfile.is_none() {
+            config.dockerfile = document
+                .settings
+                .dockerfile
+                .as_ref()
+                .map(|path| document.workdir.join(path));
+        }
+        config
+    }
 }
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
-    let markdown = fs::read_to_string(&cli.target)
-        .with_context(|| format!("while reading {}", cli.target.display()))?;
-    let blocks = markdown::extract_blocks(&markdown)?;
-    warn_duplicate_names(&blocks);
+#[derive(Clone, Debug, Default)]
+struct WasmConfig {
+    tools: HashMap<String, PathBuf>,
+    limits: WasmLimits,
+}
 
-    let workdir = cli
-        .target
-        .parent()
-        // Relative targets such as "README.md" yield an empty parent path; treat it as cwd.
-        .filter(|path| !path.as_os_str().is_empty())
-        .map(Path::to_path_buf)
-        .unwrap_or_else(|| PathBuf::from("."));
+impl WasmConfig {
+    fn from_cli(cli: &Cli) -> Self {
+        Self {
+            tools: cli.wasm_tools.iter().cloned().collect(),
+            limits: WasmLimits {
+                fuel: cli.wasm_fuel,
+                memory_bytes: mebibytes(cli.wasm_memory).expect("checked by parse_mebibytes"),
+            },
+        }
+    }
+}
 
-    let docker_config = DockerConfig::from_cli(&cli);
+fn parse_container_runtime(raw: &str) -> Result<ContainerRuntime, String> {
+    if raw.trim().is_empty() {
+        return Err("expected docker, podman, nerdctl or a path to a container CLI".to_string());
+    }
+    Ok(ContainerRuntime::new(raw))
+}
 
-    match &cli.command {
-        Some(Command::List) => render_list(&blocks),
-        Some(Command::Run(run_args)) => {
-            run_blocks(&blocks, &workdir, run_args, cli.sandbox, &docker_config)?
+fn parse_wasm_tool(raw: &str) -> Result<(String, PathBuf), String> {
+    match raw.split_once('=') {
+        Some((name, path)) if !name.is_empty() && !path.is_empty() => {
+            Ok((name.to_string(), PathBuf::from(path)))
         }
-        None => run_blocks(&blocks, &workdir, &cli.run, cli.sandbox, &docker_config)?,
+        _ => Err(format!("expected NAME=PATH, got `{raw}`")),
     }
+}
 
-    Ok(())
+/// `mib` in bytes, unless that does not fit in a `usize`.
+fn mebibytes(mib: usize) -> Option<usize> {
+    mib.checked_mul(1 << 20)
 }
 
-fn render_list(blocks: &[CodeBlock]) {
-    println!("Discovered {} block(s):", blocks.len());
-    for block in blocks {
-        let label = block.language.clone().unwrap_or_else(|| "shell".into());
-        let headings = if block.headings.is_empty() {
-            "(root)".to_string()
+fn parse_mebibytes(raw: &str) -> Result<usize, String> {
+    let mib = raw
+        .parse::<usize>()
+        .map_err(|err| format!("expected a size in MiB: {err}"))?;
+    match mebibytes(mib) {
+        Some(_) => Ok(mib),
+        None => Err(format!("{mib} MiB is too large")),
+    }
+}
+
+/// A markdown file together with the blocks parsed from it.
+struct Document {
+    path: PathBuf,
+    workdir: PathBuf,
+    blocks: Vec<CodeBlock>,
+    settings: DocumentSettings,
+}
+
+impl Document {
+    fn load(path: &Path) -> Result<Self> {
+        let ParsedDocument { blocks, settings } = markdown::read_document(path)?;
+        let workdir = path
+            .parent()
+            // Relative targets such as "README.md" yield an empty parent path; treat it as cwd.
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from("."));
+        Ok(Self {
+            path: path.to_path_buf(),
+            workdir,
+            blocks,
+            settings,
+        })
+    }
+}
+
+impl Cli {
+    /// Targets from the subcommand win over top-level ones; README.md is the fallback.
+    fn targets(&self) -> Vec<PathBuf> {
+        let scoped = match &self.command {
+            Some(Command::List(args)) => &args.targets,
+            Some(Command::Run(args)) | Some(Command::Update(args)) => &args.targets.targets,
+            None => &self.run.targets.targets,
+        };
+        let targets = if scoped.is_empty() {
+            &self.run.targets.targets
         } else {
-            block.headings.join(" › ")
+            scoped
         };
-        let display_id = if let Some(name) = &block.name {
-            format!("{} ({})", block.id, name)
+        if targets.is_empty() {
+            vec![PathBuf::from("README.md")]
         } else {
-            block.id.clone()
+            targets.clone()
+        }
+    }
+
+    /// Block filters from the subcommand, or the top-level ones when it has none.
+    fn filter(&self) -> &BlockFilter {
+        let scoped = match &self.command {
+            Some(Command::List(args)) => &args.filter,
+            Some(Command::Run(args)) | Some(Command::Update(args)) => &args.targets.filter,
+            None => &self.run.targets.filter,
         };
-        let skip_hint = block
-            .skip_reason
-            .as_ref()
-            .map(|reason| format!(" (skip: {reason})"))
-            .unwrap_or_default();
-        println!("- {} [{}] {headings}{skip_hint}", display_id, label);
+        if scoped.is_empty() {
+            &self.run.targets.filter
+        } else {
+            scoped
+        }
+    }
+}
+
+/// Overall result of a run, mapped onto the process exit status.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum RunOutcome {
+    Passed,
+    BlocksFailed,
+    NoBlocksMatched,
+}
+
+impl RunOutcome {
+    fn exit_code(self, allow_failures: bool) -> ExitCode {
+        match self {
+            RunOutcome::Passed => ExitCode::SUCCESS,
+            RunOutcome::BlocksFailed if allow_failures => ExitCode::SUCCESS,
+            RunOutcome::BlocksFailed => ExitCode::from(EXIT_BLOCKS_FAILED),
+            RunOutcome::NoBlocksMatched => ExitCode::from(EXIT_NO_BLOCKS_MATCHED),
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(&cli) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            ExitCode::from(EXIT_INTERNAL_ERROR)
+        }
+    }
+}
+
+fn run(cli: &Cli) -> Result<ExitCode> {
+    let paths = discovery::discover(&cli.targets())?;
+    let documents = paths
+        .iter()
+        .map(|path| Document::load(path))
+        .collect::<Result<Vec<_>>>()?;
+    let format = match &cli.command {
+        Some(Command::Run(run_args) | Command::Update(run_args)) => run_args.format,
+        Some(Command::List(_)) => ReportFormat::Human,
+        None => cli.run.format,
+    };
+    for document in &documents {
+        warn_duplicate_names(document, format);
+    }
+
+    let docker_config = DockerConfig::from_cli(cli);
+    let wasm_config = WasmConfig::from_cli(cli);
+
+    let (run_args, update) = match &cli.command {
+        Some(Command::List(_)) => {
+            render_list(&documents, cli.filter());
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Run(run_args)) => (run_args, false),
+        Some(Command::Update(run_args)) => (run_args, true),
+        None => (&cli.run, false),
+    };
+    if run_args.output.is_some() && !run_args.format.is_document() {
+        bail!("--output only applies to --format json, junit, html or markdown");
+    }
+    let mut events: Vec<Box<dyn ExecEvents>> = vec![match run_args.format {
+        ReportFormat::Human => Box::new(HumanStreamer::default()),
+        ReportFormat::Tap => Box::new(report::tap::TapStream::new(std::io::stdout())),
+        ReportFormat::Jsonl => Box::new(report::jsonl::JsonlStream::new(std::io::stdout())),
+        ReportFormat::Github => Box::new(report::github::GithubStream::new(std::io::stdout())),
+        ReportFormat::Json | ReportFormat::Junit | ReportFormat::Html | ReportFormat::Markdown => {
+            Box::new(())
+        }
+    }];
+    // Streaming artifacts are opened up front so they fill in while blocks run.
+    for spec in &run_args.reports {
+        match spec.format {
+            ReportFormat::Tap => events.push(Box::new(report::tap::TapStream::new(
+                create_report_file(&spec.path)?,
+            ))),
+            ReportFormat::Jsonl => events.push(Box::new(report::jsonl::JsonlStream::new(
+                create_report_file(&spec.path)?,
+            ))),
+            _ => {}
+        }
+    }
+    let started = Instant::now();
+    let result = run_blocks(
+        &documents,
+        run_args,
+        cli.sandbox,
+        &docker_config,
+        &wasm_config,
+        cli.filter(),
+        &mut events,
+    );
+    let reports = match result {
+        Ok(Some(reports)) => reports,
+        Ok(None) => return Ok(RunOutcome::NoBlocksMatched.exit_code(run_args.allow_failures)),
+        Err(Aborted { error, reports }) => {
+            events.run_aborted(&error);
+            // Artifacts still show what ran; failing to write them must not hide the runme error.
+            if let Err(write_err) = write_report_files(&documents, &reports, &run_args.reports) {
+                eprintln!("error: {write_err:#}");
+            }
+            return Err(error);
+        }
+    };
+    emit_report(
+        &documents,
+        &reports,
+        run_args.format,
+        run_args.output.as_deref(),
+    )?;
+    if let ReportFormat::Human = run_args.format {
+        print!(
+            "{}",
+            report::human::summary(&reports, started.elapsed(), run_args.slowest)
+        );
+    }
+    write_report_files(&documents, &reports, &run_args.reports)?;
+    if update {
+        update_expectations(&documents, &reports)?;
+    }
+
+    // In update mode, output drift has just been written back and no longer counts as failure.
+    let failed = reports.iter().any(|report| {
+        report.status.is_failure() && !(update && report.updated_expectation.is_some())
+    });
+    let outcome = if failed {
+        RunOutcome::BlocksFailed
+    } else {
+        RunOutcome::Passed
+    };
+    Ok(outcome.exit_code(run_args.allow_failures))
+}
+
+fn render_list(documents: &[Document], filter: &BlockFilter) {
+    for document in documents {
+        let blocks = filter.select(&document.path, &document.blocks);
+        println!(
+            "Discovered {} block(s) in {}:",
+            blocks.len(),
+            document.path.display()
+        );
+        for block in blocks {
+            let label = block.language.clone().unwrap_or_else(|| "shell".into());
+            let headings = if block.headings.is_empty() {
+                "(root)".to_string()
+            } else {
+                block.headings.join(" › ")
+            };
+            let display_id = if let Some(name) = &block.name {
+                format!("{} ({})", block.id, name)
+            } else {
+                block.id.clone()
+            };
+            let skip_hint = block
+                .skip_reason
+                .as_ref()
+                .map(|reason| format!(" (skip: {reason})"))
+                .unwrap_or_default();
+            let tags = if block.tags.is_empty() {
+                String::new()
+            } else {
+                format!(" #{}", block.tags.join(" #"))
+            };
+            let phase = match block.phase {
+                Phase::Setup => " (setup)",
+                Phase::Main => "",
+                Phase::Teardown => " (teardown)",
+            };
+            let needs = if block.needs.is_empty() {
+                String::new()
+            } else {
+                format!(" (needs: {})", block.needs.join(", "))
+            };
+            let location = source_ref(Some(&document.path), block.location.line);
+            println!(
+                "- {} [{}]{phase} {location} {headings}{tags}{needs}{skip_hint}",
+                display_id, label
+            );
+        }
+    }
+}
+
+/// A run that runme stopped on an error, with the reports to write on the way out.
+struct Aborted {
+    error: anyhow::Error,
+    /// Blocks that ran, plus every selected block runme never reached, marked as not run.
+    reports: Vec<BlockReport>,
+}
+
+impl From<anyhow::Error> for Aborted {
+    fn from(error: anyhow::Error) -> Self {
+        Self {
+            error,
+            reports: Vec::new(),
+        }
     }
 }
 
 fn run_blocks(
-    blocks: &[CodeBlock],
-    workdir: &Path,
+    documents: &[Document],
     run_args: &RunArgs,
     sandbox_kind: SandboxChoice,
     docker_config: &DockerConfig,
-) -> Result<()> {
-    let subset: Vec<&CodeBlock> = match run_args.block.as_deref() {
-        Some(key) => {
-            let block = blocks
-                .iter()
-                .find(|block| block.id == key || block.name.as_deref() == Some(key))
-                .with_context(|| format!("unknown block id or name {key}"))?;
-            vec![block]
+    wasm_config: &WasmConfig,
+    filter: &BlockFilter,
+    events: &mut dyn ExecEvents,
+) -> Result<Option<Vec<BlockReport>>, Aborted> {
+    let selections: Vec<(&Document, Vec<&CodeBlock>)> = documents
+        .iter()
+        .map(|document| {
+            let selected = filter.select(&document.path, &document.blocks);
+            let ordered = select::plan(&document.blocks, &selected)
+                .with_context(|| format!("in {}", document.path.display()))?;
+            Ok((document, ordered))
+        })
+        .collect::<Result<_>>()?;
+    let all: Vec<&CodeBlock> = documents
+        .iter()
+        .flat_map(|document| &document.blocks)
+        .collect();
+    // A mistyped `--block` fails the run even when the other keys matched something.
+    let unknown = filter.unknown_keys(&all);
+    if !unknown.is_empty() {
+        eprintln!("error: unknown block id or name {}", unknown.join(", "));
+        return Ok(None);
+    }
+    if selections.iter().all(|(_, subset)| subset.is_empty()) {
+        if all.is_empty() {
+            eprintln!("error: no code blocks found in the given targets");
+        } else {
+            eprintln!("error: no code blocks match the given filters");
         }
-        None => blocks.iter().collect(),
+        return Ok(None);
+    }
+
+    let options = ExecOptions {
+        default_mode: run_args.mode,
+        shared_session: run_args.session,
+        default_timeout: run_args.timeout,
+        normalize: Vec::new(),
     };
+    let files: Vec<PathBuf> = selections
+        .iter()
+        .filter(|(_, subset)| !subset.is_empty())
+        .map(|(document, _)| document.path.clone())
+        .collect();
+    let total = selections.iter().map(|(_, subset)| subset.len()).sum();
+    let started = Instant::now();
+    events.run_started(&files, total);
 
-    let mut sandbox = instantiate_sandbox(workdir, sandbox_kind, docker_config)?;
-    let stream_live = matches!(run_args.format, ReportFormat::Human);
     let mut reports = Vec::new();
-    for block in subset {
-        let report = runner::execute(block, sandbox.as_mut(), stream_live)
-            .with_context(|| format!("while running {}", block.id))?;
-        reports.push(report);
+    let mut aborted: Option<anyhow::Error> = None;
+    for (document, subset) in &selections {
+        if subset.is_empty() {
+            continue;
+        }
+        let mut sandbox = match instantiate_sandbox(
+            &document.workdir,
+            sandbox_kind,
+            &docker_config.for_document(document),
+            wasm_config,
+        ) {
+            Ok(sandbox) => sandbox,
+            Err(err) => {
+                aborted = Some(err);
+                break;
+            }
+        };
+        let options = ExecOptions {
+            normalize: document.settings.normalize.clone(),
+            ..options.clone()
+        };
+        // Sessions never outlive their document; dropping the pool stops the shells.
+        let mut sessions = SessionPool::default();
+        // Blocks that did not pass: failed, or skipped for any reason (including `runme:ignore`).
+        let mut blocked: HashSet<&str> = HashSet::new();
+        let mut failed_setup: Option<&str> = None;
+        // The first runme error in this document; only teardown blocks run after it.
+        let mut error: Option<anyhow::Error> = None;
+        for &block in subset {
+            if error.is_some() && block.phase != Phase::Teardown {
+                continue;
+            }
+            let failed_need = block
+                .needs
+                .iter()
+                .filter_map(|key| select::find(&document.blocks, key))
+                .find(|need| blocked.contains(need.id.as_str()));
+            // Teardown always runs so it can clean up after whatever did happen.
+            let skip_reason = match (block.phase, failed_setup, failed_need) {
+                (Phase::Teardown, _, _) => None,
+                (Phase::Main, Some(setup), _) => Some(format!("Setup {setup} did not pass")),
+                (_, _, Some(need)) => Some(format!("Prerequisite {} did not pass", need.id)),
+                _ => None,
+            };
+            let report = match skip_reason {
+                Some(reason) => runner::skip(block, reason, events),
+                None => {
+                    match runner::execute(block, sandbox.as_mut(), &mut sessions, &options, events)
+                    {
+                        Ok(report) => report,
+                        Err(err) => {
+                            error.get_or_insert(err.context(format!(
+                                "while running {} in {}",
+                                block.id,
+                                document.path.display()
+                            )));
+                            continue;
+                        }
+                    }
+                }
+            };
+            if report.status.is_failure() || report.status == BlockStatus::Skipped {
+                blocked.insert(&block.id);
+            }
+            // A skipped setup block (e.g. `runme:ignore`) only holds back blocks that need it.
+            if report.status.is_failure() && block.phase == Phase::Setup {
+                failed_setup.get_or_insert(&block.id);
+            }
+            reports.push(report);
+        }
+        if error.is_some() {
+            aborted = error;
+            break;
+        }
+    }
+    if let Some(error) = aborted {
+        for &block in selections.iter().flat_map(|(_, subset)| subset) {
+            let reached = reports
+                .iter()
+                .any(|report| report.file == block.file && report.id == block.id);
+            if !reached {
+                let reason = "Not run: runme stopped on an error".to_string();
+                reports.push(runner::skip(block, reason, &mut ()));
+            }
+        }
+        return Err(Aborted { error, reports });
     }
+    events.run_finished(&reports, started.elapsed());
 
-    match run_args.format {
-        ReportFormat::Human => {
-            for report in &reports {
-                print_human_report(report, stream_live);
+    Ok(Some(reports))
+}
+
+/// Render formats that produce one document at the end of the run; `None` for the others.
+fn render_report(
+    documents: &[Document],
+    reports: &[BlockReport],
+    format: ReportFormat,
+) -> Result<Option<String>> {
+    let rendered = match format {
+        ReportFormat::Json => serde_json::to_string_pretty(reports)? + "\n",
+        ReportFormat::Junit => report::junit::render(reports),
+        ReportFormat::Html => {
+            let sources = documents
+                .iter()
+                .map(|document| {
+                    fs::read_to_string(&document.path)
+                        .with_context(|| format!("while re-reading {}", document.path.display()))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let pages: Vec<_> = documents
+                .iter()
+                .zip(&sources)
+                .map(|(document, markdown)| report::html::Page {
+                    path: &document.path,
+                    markdown,
+                })
+                .collect();
+            report::html::render(&pages, reports)?
+        }
+        ReportFormat::Markdown => {
+            let blocks: Vec<&CodeBlock> = documents
+                .iter()
+                .flat_map(|document| &document.blocks)
+                .collect();
+            report::markdown::render(reports, &blocks)
+        }
+        // Human output is printed per block below; the rest streamed while blocks ran.
+        ReportFormat::Human | ReportFormat::Tap | ReportFormat::Jsonl | ReportFormat::Github => {
+            return Ok(None);
+        }
+    };
+    Ok(Some(rendered))
+}
+
+/// Print the end-of-run report, or write it to `output` when given.
+fn emit_report(
+    documents: &[Document],
+    reports: &[BlockReport],
+    format: ReportFormat,
+    output: Option<&Path>,
+) -> Result<()> {
+    let Some(rendered) = render_report(documents, reports, format)? else {
+        if let ReportFormat::Human = format {
+            for report in reports {
+                print_human_report(report, true);
             }
         }
-        ReportFormat::Json => {
-            let json = serde_json::to_string_pretty(&reports)?;
-            println!("{json}");
+        return Ok(());
+    };
+    match output {
+        Some(path) => write_report(path, &rendered)?,
+        None => print!("{rendered}"),
+    }
+    Ok(())
+}
+
+/// Render every document-style `--report` artifact once the run is over.
+fn write_report_files(
+    documents: &[Document],
+    reports: &[BlockReport],
+    specs: &[ReportSpec],
+) -> Result<()> {
+    for spec in specs {
+        if let Some(rendered) = render_report(documents, reports, spec.format)? {
+            write_report(&spec.path, &rendered)?;
         }
     }
+    Ok(())
+}
+
+fn write_report(path: &Path, rendered: &str) -> Result<()> {
+    create_parent_dir(path)?;
+    fs::write(path, rendered).with_context(|| format!("while writing report to {}", path.display()))
+}
+
+fn create_report_file(path: &Path) -> Result<fs::File> {
+    create_parent_dir(path)?;
+    fs::File::create(path).with_context(|| format!("while creating report {}", path.display()))
+}
+
+fn create_parent_dir(path: &Path) -> Result<()> {
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("while creating {}", parent.display()))?;
+    }
+    Ok(())
+}
+
+/// Rewrite `runme:expect` blocks whose output drifted, leaving the rest of each file untouched.
+fn update_expectations(documents: &[Document], reports: &[BlockReport]) -> Result<()> {
+    for document in documents {
+        let updates: HashMap<&str, &str> = reports
+            .iter()
+            .filter(|report| report.file.as_deref() == Some(document.path.as_path()))
+            .filter_map(|report| Some((report.id.as_str(), report.updated_expectation.as_deref()?)))
+            .collect();
+        if updates.is_empty() {
+            continue;
+        }
 
+        // Re-parse the current file so the byte ranges match what is on disk right now.
+        let source = fs::read_to_string(&document.path)
+            .with_context(|| format!("while re-reading {}", document.path.display()))?;
+        let edits: Vec<_> = markdown::extract_blocks(&source)?
+            .into_iter()
+            .filter_map(|block| {
+                let contents = updates.get(block.id.as_str())?;
+                Some((block.expected_span?, contents.to_string()))
+            })
+            .collect();
+        fs::write(&document.path, markdown::replace_contents(&source, &edits))
+            .with_context(|| format!("while writing {}", document.path.display()))?;
+        // Stdout carries the report, which must stay parseable.
+        eprintln!(
+            "updated {} expected output block(s) in {}",
+            edits.len(),
+            document.path.display()
+        );
+    }
     Ok(())
 }
 
@@ -194,36 +830,128 @@ fn instantiate_sandbox(
     workdir: &Path,
     kind: SandboxChoice,
     docker: &DockerConfig,
+    wasm: &WasmConfig,
 ) -> Result<Box<dyn Sandbox>> {
     match kind {
         SandboxChoice::Host => Ok(Box::new(HostSandbox::new(workdir))),
-        SandboxChoice::Docker => Ok(Box::new(DockerSandbox::new(
+        SandboxChoice::Docker => {
+            let image = match &docker.dockerfile {
+                Some(dockerfile) => Some(runner::build_image(&docker.runtime, dockerfile)?),
+                None => docker.image.clone(),
+            };
+            Ok(Box::new(DockerSandbox::new(
+                workdir,
+                docker.runtime.clone(),
+                image,
+                docker.extra_args.clone(),
+            )))
+        }
+        SandboxChoice::Wasm => Ok(Box::new(WasmSandbox::new(
             workdir,
-            docker.image.clone(),
-            docker.extra_args.clone(),
-        ))),
-        SandboxChoice::Wasm => Ok(Box::new(WasmSandbox::new(workdir))),
+            wasm.tools.clone(),
+            wasm.limits,
+        )?)),
+        #[cfg(target_os = "linux")]
+        SandboxChoice::Ns => Ok(Box::new(runner::NsSandbox::new(workdir)?)),
+        #[cfg(not(target_os = "linux"))]
+        SandboxChoice::Ns => bail!("--sandbox ns needs Linux namespaces"),
     }
 }
 
-fn warn_duplicate_names(blocks: &[CodeBlock]) {
-    let mut map: HashMap<&str, Vec<&str>> = HashMap::new();
-    for block in blocks {
+fn warn_duplicate_names(document: &Document, format: ReportFormat) {
+    let mut map: HashMap<&str, Vec<&CodeBlock>> = HashMap::new();
+    for block in &document.blocks {
         if let Some(name) = block.name.as_deref() {
-            map.entry(name).or_default().push(block.id.as_str());
+            map.entry(name).or_default().push(block);
         }
     }
 
-    let mut duplicates: Vec<_> = map.into_iter().filter(|(_, ids)| ids.len() > 1).collect();
+    let mut duplicates: Vec<_> = map
+        .into_iter()
+        .filter(|(_, blocks)| blocks.len() > 1)
+        .collect();
     duplicates.sort_by_key(|(name, _)| *name);
 
-    for (name, ids) in duplicates {
-        let list = ids.join(", ");
-        eprintln!(
-            "warning: runme:name '{}' is used by multiple blocks ({list}); `--block {name}` will target the first match.",
-            name
+    for (name, blocks) in duplicates {
+        let list = blocks
+            .iter()
+            .map(|block| block.id.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let message = format!(
+            "runme:name '{name}' is used by multiple blocks ({list}); `--block {name}` will target the first match."
+        );
+        match format {
+            // Annotate the first shadowed block, the one `--block` can no longer reach.
+            ReportFormat::Github => println!(
+                "{}",
+                report::github::warning(
+                    Some(&document.path),
+                    blocks[1].location.line,
+                    "duplicate runme:name",
+                    &message
+                )
+            ),
+            _ => eprintln!("warning: {message}"),
+        }
+    }
+}
+
+fn print_human_report(report: &BlockReport, streamed: bool) {
+    let header = if let Some(name) = &report.name {
+        format!("{} ({})", report.id, name)
+    } else {
+        report.id.clone()
+    };
+    match report.phase {
+        Phase::Setup => println!("\n== setup {header} =="),
+        Phase::Main => println!("\n== {header} =="),
+        Phase::Teardown => println!("\n== teardown {header} =="),
+    }
+    println!(
+        "location: {}",
+        source_ref(report.file.as_deref(), report.location.line)
+    );
+    if let Some(lang) = &report.language {
+        println!("language: {lang}");
+    }
+    if let Some(sandbox) = &report.sandbox {
+        println!("sandbox: {sandbox}");
+    }
+    if let Some(session) = &report.session {
+        println!("session: {session}");
+    }
+    if !report.headings.is_empty() {
+        println!("context: {}", report.headings.join(" › "));
+    }
+    println!("status: {:?}", report.status);
+    if let (Some(line), Some(source_line)) = (report.failed_line, report.failed_source_line) {
+        println!(
+            "failed at {} (block line {line})",
+            source_ref(report.file.as_deref(), source_line)
         );
     }
+    if let BlockStatus::TimedOut { timeout_ms } = report.status {
+        println!("timed out after {timeout_ms}ms; killed the block's processes");
+    }
+    if let Some(diff) = &report.output_diff {
+        println!("stdout differs from runme:expect block:\n{diff}");
+    }
+    if let Some(reason) = &report.skip_reason {
+        println!("skip reason: {reason}");
+    }
+    if !streamed {
+        if let Some(stdout) = &report.stdout
+            && !stdout.is_empty()
+        {
+            println!("stdout:\n{stdout}");
+        }
+        if let Some(stderr) = &report.stderr
+            && !stderr.is_empty()
+        {
+            println!("stderr:\n{stderr}");
+        }
+    }
 }
 
 #[cfg(test)]
@@ -247,6 +975,14 @@ mod tests {
         assert_eq!(wasm.sandbox, SandboxChoice::Wasm);
     }
 
+    #[test]
+    fn wasm_memory_is_given_in_mib_and_must_fit() {
+        let cli = Cli::try_parse_from(["runme", "--wasm-memory", "64", "list"]).expect("parse");
+        assert_eq!(WasmConfig::from_cli(&cli).limits.memory_bytes, 64 << 20);
+        let huge = usize::MAX.to_string();
+        assert!(Cli::try_parse_from(["runme", "--wasm-memory", huge.as_str(), "list"]).is_err());
+    }
+
     #[test]
     fn docker_cli_flags_capture_configuration() {
         let cli = Cli::try_parse_from([
@@ -261,71 +997,421 @@ mod tests {
         ])
         .expect("parse docker options");
         assert_eq!(cli.docker_image.as_deref(), Some("custom:tag"));
+        assert_eq!(cli.container_runtime, ContainerRuntime::default());
         assert_eq!(
             cli.docker_args,
             vec!["--env=FOO=bar".to_string(), "--cpus=1".to_string()]
         );
     }
 
+    #[test]
+    fn dockerfile_comes_from_the_cli_or_the_document() {
+        let parsed = markdown::parse_document(
+            "<!-- runme:dockerfile ci/Dockerfile -->\n```bash\nmake\n```\n",
+        )
+        .expect("parse");
+        let document = Document {
+            path: PathBuf::from("docs/guide.md"),
+            workdir: PathBuf::from("docs"),
+            blocks: parsed.blocks,
+            settings: parsed.settings,
+        };
+        let from_doc = DockerConfig::default().for_document(&document);
+        assert_eq!(
+            from_doc.dockerfile.as_deref(),
+            Some(Path::new("docs/ci/Dockerfile"))
+        );
+
+        let cli = Cli::try_parse_from(["runme", "--docker-build", "Dockerfile.ci", "list"])
+            .expect("parse docker build");
+        let from_cli = DockerConfig::from_cli(&cli).for_document(&document);
+        assert_eq!(
+            from_cli.dockerfile.as_deref(),
+            Some(Path::new("Dockerfile.ci"))
+        );
+
+        let pinned = DockerConfig {
+            image: Some("alpine:3.19".into()),
+            ..DockerConfig::default()
+        };
+        assert_eq!(pinned.for_document(&document).dockerfile, None);
+        assert!(
+            Cli::try_parse_from([
+                "runme",
+                "--docker-build",
+                "D",
+                "--docker-image",
+                "i",
+                "list"
+            ])
+            .is_err()
+        );
+    }
+
     #[test]
     fn run_args_parse_without_run_subcommand() {
         let cli = Cli::try_parse_from(["runme", "--block", "block-123"])
             .expect("parse implicit run options");
         assert!(cli.command.is_none());
-        assert_eq!(cli.run.block.as_deref(), Some("block-123"));
+        assert_eq!(cli.run.targets.filter.block, ["block-123"]);
+    }
+
+    #[test]
+    fn report_flags_collect_format_and_path_pairs() {
+        let cli = Cli::try_parse_from([
+            "runme",
+            "run",
+            "--report",
+            "json=out/runme.json",
+            "--report",
+            "JUnit=out/junit.xml",
+        ])
+        .expect("parse reports");
+        let Some(Command::Run(args)) = cli.command else {
+            panic!("expected run subcommand");
+        };
+        assert_eq!(args.format, ReportFormat::Human, "live output is unchanged");
+        assert_eq!(
+            args.reports,
+            vec![
+                ReportSpec {
+                    format: ReportFormat::Json,
+                    path: PathBuf::from("out/runme.json"),
+                },
+                ReportSpec {
+                    format: ReportFormat::Junit,
+                    path: PathBuf::from("out/junit.xml"),
+                },
+            ]
+        );
+
+        for invalid in ["out/runme.json", "json=", "yaml=out.yml", "human=out.txt"] {
+            assert!(
+                Cli::try_parse_from(["runme", "--report", invalid]).is_err(),
+                "{invalid} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn targets_resolve_from_subcommand_then_top_level() {
+        let run = Cli::try_parse_from(["runme", "run", "docs/", "README.md", "guides/**/*.md"])
+            .expect("parse run targets");
+        assert_eq!(
+            run.targets(),
+            vec![
+                PathBuf::from("docs/"),
+                PathBuf::from("README.md"),
+                PathBuf::from("guides/**/*.md"),
+            ]
+        );
+
+        let list = Cli::try_parse_from(["runme", "list", "docs/"]).expect("parse list targets");
+        assert_eq!(list.targets(), vec![PathBuf::from("docs/")]);
+
+        let bare = Cli::try_parse_from(["runme"]).expect("parse without targets");
+        assert_eq!(bare.targets(), vec![PathBuf::from("README.md")]);
+    }
+
+    #[test]
+    fn allow_failures_only_masks_block_failures() {
+        let cli = Cli::try_parse_from(["runme", "run", "--allow-failures"])
+            .expect("parse allow-failures");
+        match cli.command {
+            Some(Command::Run(args)) => assert!(args.allow_failures),
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        assert_eq!(
+            RunOutcome::BlocksFailed.exit_code(false),
+            ExitCode::from(EXIT_BLOCKS_FAILED)
+        );
+        assert_eq!(RunOutcome::BlocksFailed.exit_code(true), ExitCode::SUCCESS);
+        assert_eq!(
+            RunOutcome::NoBlocksMatched.exit_code(true),
+            ExitCode::from(EXIT_NO_BLOCKS_MATCHED)
+        );
+        assert_eq!(RunOutcome::Passed.exit_code(false), ExitCode::SUCCESS);
+    }
+
+    /// Run `markdown` as `guide.md` on the host with extra command-line `args`.
+    fn run_guide(markdown: &str, args: &[&str]) -> Result<Vec<BlockReport>> {
+        Ok(run_selection(markdown, args)?.expect("blocks matched"))
+    }
+
+    /// Like `run_guide`, but `None` when runme would exit because no blocks matched.
+    fn run_selection(markdown: &str, args: &[&str]) -> Result<Option<Vec<BlockReport>>> {
+        let documents = vec![Document {
+            path: PathBuf::from("guide.md"),
+            workdir: PathBuf::from("."),
+            blocks: markdown::extract_blocks(markdown).expect("parse"),
+            settings: DocumentSettings::default(),
+        }];
+        let cli = Cli::try_parse_from(std::iter::once("runme").chain(args.iter().copied()))
+            .expect("parse args");
+        run_blocks(
+            &documents,
+            &cli.run,
+            SandboxChoice::Host,
+            &DockerConfig::default(),
+            &WasmConfig::default(),
+            cli.filter(),
+            &mut (),
+        )
+        .map_err(|aborted| aborted.error)
+    }
+
+    #[test]
+    fn unknown_block_keys_fail_even_when_others_match() {
+        let markdown = "```bash runme:name=smoke\necho smoke\n```\n";
+        let run = |args: &[&str]| run_selection(markdown, args).expect("run");
+        assert!(run(&["--block", "smoke"]).is_some());
+        assert!(
+            run(&["--block", "smoke", "--block", "typo-name"]).is_none(),
+            "a typo must not pass silently"
+        );
+    }
+
+    #[test]
+    fn needs_run_prerequisites_once_and_skip_dependents_of_failures() {
+        let markdown = "```bash runme:name=deps\necho deps\n```\n\n```bash runme:name=build runme:needs=deps\nfalse\n```\n\n```bash runme:needs=build,deps\necho serve\n```\n\n```bash runme:needs=deps\necho docs\n```\n";
+        let reports =
+            run_guide(markdown, &["--block", "block-003", "--block", "block-004"]).expect("run");
+
+        let outcomes: Vec<(&str, &BlockStatus)> = reports
+            .iter()
+            .map(|report| (report.id.as_str(), &report.status))
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                ("block-001", &BlockStatus::Passed),
+                ("block-002", &BlockStatus::Failed { exit_code: Some(1) }),
+                ("block-003", &BlockStatus::Skipped),
+                ("block-004", &BlockStatus::Passed),
+            ]
+        );
+        assert_eq!(
+            reports[2].skip_reason.as_deref(),
+            Some("Prerequisite block-002 did not pass")
+        );
+
+        let markdown = "```bash runme:name=deps runme:ignore\necho deps\n```\n\n```python runme:name=seed\nprint(1)\n```\n\n```bash runme:needs=deps\necho build\n```\n\n```bash runme:needs=seed\necho test\n```\n";
+        let reports =
+            run_guide(markdown, &["--block", "block-003", "--block", "block-004"]).expect("run");
+        let reasons: Vec<(&str, Option<&str>)> = reports
+            .iter()
+            .map(|report| (report.id.as_str(), report.skip_reason.as_deref()))
+            .collect();
+        assert!(
+            reports
+                .iter()
+                .all(|report| report.status == BlockStatus::Skipped)
+        );
+        assert_eq!(
+            reasons,
+            [
+                ("block-001", Some("Marked with runme:ignore")),
+                ("block-003", Some("Prerequisite block-001 did not pass")),
+                (
+                    "block-002",
+                    Some("Language 'python' unsupported yet; add a plugin")
+                ),
+                ("block-004", Some("Prerequisite block-002 did not pass")),
+            ]
+        );
+    }
+
+    #[test]
+    fn setup_and_teardown_wrap_any_selection() {
+        let run = |setup: &str| {
+            let markdown = format!(
+                "<!-- runme:teardown -->\n```bash\necho cleanup\n```\n\n```bash runme:name=test\necho test\n```\n\n```bash\necho other\n```\n\n```bash runme:setup\n{setup}\n```\n"
+            );
+            run_guide(&markdown, &["--block", "test"])
+                .expect("run")
+                .into_iter()
+                .map(|report| (report.id, report.phase, report.status))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            run("true"),
+            [
+                ("block-004".into(), Phase::Setup, BlockStatus::Passed),
+                ("block-002".into(), Phase::Main, BlockStatus::Passed),
+                ("block-001".into(), Phase::Teardown, BlockStatus::Passed),
+            ]
+        );
+        assert_eq!(
+            run("false"),
+            [
+                (
+                    "block-004".into(),
+                    Phase::Setup,
+                    BlockStatus::Failed { exit_code: Some(1) }
+                ),
+                ("block-002".into(), Phase::Main, BlockStatus::Skipped),
+                ("block-001".into(), Phase::Teardown, BlockStatus::Passed),
+            ]
+        );
+    }
+
+    #[test]
+    fn skipped_setup_only_holds_back_blocks_that_need_it() {
+        let markdown = "```bash runme:setup runme:ignore runme:name=seed\necho seed\n```\n\n```bash\necho test\n```\n\n```bash runme:needs=seed\necho seeded\n```\n";
+        let outcomes: Vec<_> = run_guide(markdown, &[])
+            .expect("run")
+            .into_iter()
+            .map(|report| (report.id, report.status))
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                ("block-001".into(), BlockStatus::Skipped),
+                ("block-002".into(), BlockStatus::Passed),
+                ("block-003".into(), BlockStatus::Skipped),
+            ]
+        );
+    }
+
+    #[test]
+    fn teardown_runs_after_runme_errors() {
+        let marker = std::env::temp_dir().join(format!("runme-teardown-{}", std::process::id()));
+        let markdown = format!(
+            "```bash\nrunme-no-such-command\n```\n\n```bash\necho skipped\n```\n\n```bash runme:teardown\ntouch {}\n```\n",
+            marker.display()
+        );
+        let err = run_guide(&markdown, &[]).expect_err("the typo is a runme error");
+        assert!(err.to_string().contains("block-001"), "{err:#}");
+        assert!(marker.exists(), "teardown ran before the error surfaced");
+        fs::remove_file(marker).unwrap();
+    }
+
+    #[test]
+    fn report_files_are_written_when_runme_stops() {
+        let dir = std::env::temp_dir().join(format!("runme-aborted-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("guide.md");
+        fs::write(
+            &path,
+            "```bash\necho ran\n```\n\n```bash\nrunme-no-such-command\n```\n\n```bash\necho never\n```\n",
+        )
+        .unwrap();
+        let json = dir.join("out/runme.json");
+        let report_arg = format!("json={}", json.display());
+        let cli = Cli::try_parse_from([
+            "runme",
+            "run",
+            path.to_str().unwrap(),
+            "--format",
+            "junit",
+            "--report",
+            &report_arg,
+        ])
+        .expect("parse run");
+
+        run(&cli).expect_err("the typo is a runme error");
+        let written: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&json).expect("report written")).unwrap();
+        let reasons: Vec<_> = written
+            .as_array()
+            .expect("report is a list")
+            .iter()
+            .map(|report| {
+                (
+                    report["id"].as_str().unwrap(),
+                    report["skip_reason"].as_str(),
+                )
+            })
+            .collect();
+        let not_run = Some("Not run: runme stopped on an error");
+        assert_eq!(
+            reasons,
+            [
+                ("block-001", None),
+                ("block-002", not_run),
+                ("block-003", not_run)
+            ]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn update_rewrites_only_drifted_expectations() {
+        let dir = std::env::temp_dir().join(format!("runme-update-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("guide.md");
+        let original = "# Guide\n\n```bash\necho fresh\n```\n\n<!-- runme:expect -->\n```text\nstale\n```\n\n```bash\necho same\n```\n\n```text output\nsame\n```\n";
+        fs::write(&path, original).unwrap();
+
+        let cli =
+            Cli::try_parse_from(["runme", "update", path.to_str().unwrap()]).expect("parse update");
+        let Some(Command::Update(run_args)) = &cli.command else {
+            panic!("expected update subcommand");
+        };
+        let documents = vec![Document::load(&path).expect("load guide")];
+        let reports = run_blocks(
+            &documents,
+            run_args,
+            SandboxChoice::Host,
+            &DockerConfig::default(),
+            &WasmConfig::default(),
+            &run_args.targets.filter,
+            &mut (),
+        )
+        .map_err(|aborted| aborted.error)
+        .expect("run guide")
+        .expect("blocks matched");
+        update_expectations(&documents, &reports).expect("rewrite guide");
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            original.replace("stale", "fresh")
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn source_refs_point_into_files() {
+        assert_eq!(
+            source_ref(Some(Path::new("docs/guide.md")), 42),
+            "docs/guide.md:42"
+        );
+        assert_eq!(source_ref(None, 7), "line 7");
     }
 
     #[test]
     fn instantiate_builds_all_backends() {
         let docker_cfg = DockerConfig {
+            runtime: ContainerRuntime::new("podman"),
             image: Some("alpine:3.19".into()),
+            dockerfile: None,
             extra_args: vec!["--cpus=1".into()],
         };
-        let host = instantiate_sandbox(Path::new("."), SandboxChoice::Host, &docker_cfg)
+        let wasm_cfg = WasmConfig::default();
+        let host = instantiate_sandbox(Path::new("."), SandboxChoice::Host, &docker_cfg, &wasm_cfg)
             .expect("host sandbox exists");
         assert_eq!(host.label(), "host");
 
-        let docker =
-            instantiate_sandbox(Path::new("."), SandboxChoice::Docker, &docker_cfg.clone())
-                .expect("docker sandbox exists");
-        assert_eq!(docker.label(), "docker");
+        let docker = instantiate_sandbox(
+            Path::new("."),
+            SandboxChoice::Docker,
+            &docker_cfg.clone(),
+            &wasm_cfg,
+        )
+        .expect("docker sandbox exists");
+        assert_eq!(docker.label(), "podman:alpine-3.19");
 
-        let wasm = instantiate_sandbox(Path::new("."), SandboxChoice::Wasm, &docker_cfg)
+        let wasm = instantiate_sandbox(Path::new("."), SandboxChoice::Wasm, &docker_cfg, &wasm_cfg)
             .expect("wasm sandbox exists");
-        assert_eq!(wasm.label(), "wasm(host-fallback)");
-    }
-}
+        assert_eq!(wasm.label(), "wasm");
 
-fn print_human_report(report: &BlockReport, streamed: bool) {
-    let header = if let Some(name) = &report.name {
-        format!("{} ({})", report.id, name)
-    } else {
-        report.id.clone()
-    };
-    println!("\n== {header} ==");
-    if let Some(lang) = &report.language {
-        println!("language: {lang}");
-    }
-    if let Some(sandbox) = &report.sandbox {
-        println!("sandbox: {sandbox}");
-    }
-    if !report.headings.is_empty() {
-        println!("context: {}", report.headings.join(" › "));
-    }
-    println!("status: {:?}", report.status);
-    if let Some(reason) = &report.skip_reason {
-        println!("skip reason: {reason}");
-    }
-    if !streamed {
-        if let Some(stdout) = &report.stdout {
-            if !stdout.is_empty() {
-                println!("stdout:\n{stdout}");
-            }
-        }
-        if let Some(stderr) = &report.stderr {
-            if !stderr.is_empty() {
-                println!("stderr:\n{stderr}");
-            }
+        #[cfg(target_os = "linux")]
+        {
+            let ns = instantiate_sandbox(Path::new("."), SandboxChoice::Ns, &docker_cfg, &wasm_cfg)
+                .expect("ns sandbox exists");
+            assert_eq!(ns.label(), "ns");
         }
     }
 }
@@ -4,8 +4,16 @@
 //! and inline directives, and returns structured `CodeBlock` records
 //! the CLI can later filter or execute.
 
-use anyhow::{Result, anyhow};
-use pulldown_cmark::{CodeBlockKind, CowStr, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow, bail};
+use clap::ValueEnum;
+use pulldown_cmark::{
+    CodeBlockKind, CowStr, Event, HeadingLevel, MetadataBlockKind, Options, Parser, Tag, TagEnd,
+};
 use serde::Serialize;
 
 /// Normalized metadata for a runnable block discovered in markdown.
@@ -13,6 +21,8 @@ use serde::Serialize;
 pub struct CodeBlock {
     /// Stable identifier assigned by discovery order, e.g. `block-001`.
     pub id: String,
+    /// Markdown file the block was read from; `None` for in-memory documents.
+    pub file: Option<PathBuf>,
     /// Optional human-readable name provided via `runme:name`.
     pub name: Option<String>,
     /// Language info string (lowercase) when provided.
@@ -23,6 +33,135 @@ pub struct CodeBlock {
     pub content: String,
     /// Optional explanation when a directive marks the block as non-runnable.
     pub skip_reason: Option<String>,
+    /// Execution mode requested via `runme:mode`; `None` defers to the CLI default.
+    pub mode: Option<ExecMode>,
+    /// Persistent shell requested via `runme:session`, shared with other blocks naming it.
+    pub session: Option<String>,
+    /// Wall-clock budget requested via `runme:timeout`; `None` defers to `--timeout`.
+    pub timeout: Option<Duration>,
+    /// Labels from `runme:tags=smoke,slow`, used to select blocks with `--tag`.
+    pub tags: Vec<String>,
+    /// Ids or names of blocks that must pass first, from `runme:needs=install-deps,build`.
+    pub needs: Vec<String>,
+    /// `runme:setup` and `runme:teardown` blocks run around every selection from their document.
+    pub phase: Phase,
+    /// Stdout the block must print, taken from the fenced block bound with `runme:expect`.
+    pub expected_stdout: Option<String>,
+    /// Byte range of the whole fenced block, fences included, in the source markdown.
+    pub range: Range<usize>,
+    /// Line/column span of the block in the source markdown.
+    pub location: SourceLocation,
+    /// Location of the bound `runme:expect` block's contents, used by `runme update`.
+    pub expected_span: Option<ContentSpan>,
+}
+
+/// 1-based position of a block in its markdown file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct SourceLocation {
+    /// Line of the opening fence.
+    pub line: usize,
+    /// Column (in characters) where the opening fence starts.
+    pub column: usize,
+    /// Line of the closing fence.
+    pub end_line: usize,
+    /// Column just past the closing fence.
+    pub end_column: usize,
+    /// Line holding the first line of `CodeBlock::content`.
+    pub content_line: usize,
+}
+
+impl SourceLocation {
+    /// Source line of the 1-based `block_line` within the block content.
+    pub fn source_line(&self, block_line: usize) -> usize {
+        self.content_line + block_line.saturating_sub(1)
+    }
+}
+
+/// Maps byte offsets of one markdown source to line/column pairs.
+struct LineIndex<'a> {
+    source: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    fn new(source: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(idx, _)| idx + 1))
+            .collect();
+        Self {
+            source,
+            line_starts,
+        }
+    }
+
+    /// 1-based line and character column of `offset`.
+    fn position(&self, offset: usize) -> (usize, usize) {
+        let line = self.line_starts.partition_point(|start| *start <= offset);
+        let line_start = self.line_starts[line - 1];
+        let column = self.source[line_start..offset].chars().count() + 1;
+        (line, column)
+    }
+}
+
+/// Where a fenced block's contents live in the source, so they can be rewritten in place.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ContentSpan {
+    /// Byte range covering whole content lines; empty (at the closing fence) for empty blocks.
+    pub range: Range<usize>,
+    /// Indentation or blockquote markers that precede every content line.
+    pub indent: String,
+}
+
+/// Rewrites applied to captured stdout (and expected text) before matching.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Normalization {
+    /// Drop ANSI color and cursor escape sequences.
+    StripAnsi,
+    /// Trim lines and squeeze runs of whitespace into one space.
+    CollapseWhitespace,
+    /// Replace the sandbox working directory path with `$WORKDIR`.
+    Workdir,
+}
+
+impl Normalization {
+    fn parse(raw: &str) -> Result<Self> {
+        match raw.to_ascii_lowercase().as_str() {
+            "strip-ansi" => Ok(Self::StripAnsi),
+            "collapse-whitespace" => Ok(Self::CollapseWhitespace),
+            "workdir" => Ok(Self::Workdir),
+            other => Err(anyhow!(
+                "runme:normalize expects strip-ansi, collapse-whitespace or workdir, got '{other}'"
+            )),
+        }
+    }
+}
+
+/// Session name used when `runme:session` (or `--session`) does not name one.
+pub const DEFAULT_SESSION: &str = "default";
+
+/// How the runner feeds a block to the sandbox.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecMode {
+    /// Run every non-empty line as its own process after `shlex` parsing.
+    #[default]
+    Lines,
+    /// Hand the whole block to a shell interpreter chosen from the block language.
+    Script,
+}
+
+/// When a block runs relative to the blocks selected from its document.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// Runs before any selected block, even with `--block`.
+    Setup,
+    /// An ordinary block, run when selected.
+    #[default]
+    Main,
+    /// Runs after the selected blocks, whether or not they passed.
+    Teardown,
 }
 
 impl CodeBlock {
@@ -40,33 +179,107 @@ impl CodeBlock {
     }
 }
 
+/// Settings that apply to a whole document, from directives anywhere in it or its front matter.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DocumentSettings {
+    /// `runme:dockerfile` path, relative to the markdown file, that the Docker sandbox builds
+    /// its image from.
+    pub dockerfile: Option<PathBuf>,
+    /// `runme:normalize` rules applied before comparing expected output.
+    pub normalize: Vec<Normalization>,
+}
+
+/// Blocks of one markdown document plus its document-wide settings.
+#[derive(Clone, Debug)]
+pub struct ParsedDocument {
+    pub blocks: Vec<CodeBlock>,
+    pub settings: DocumentSettings,
+}
+
+/// Read a markdown file from disk and tag every extracted block with its path.
+pub fn read_document(path: &Path) -> Result<ParsedDocument> {
+    let markdown =
+        fs::read_to_string(path).with_context(|| format!("while reading {}", path.display()))?;
+    let mut document =
+        parse_document(&markdown).with_context(|| format!("while parsing {}", path.display()))?;
+    for block in &mut document.blocks {
+        block.file = Some(path.to_path_buf());
+    }
+    Ok(document)
+}
+
 /// Parse markdown documents and surface runnable code blocks in discovery order.
 pub fn extract_blocks(markdown: &str) -> Result<Vec<CodeBlock>> {
-    let mut parser = Parser::new_ext(markdown, Options::all());
-    let mut blocks = Vec::new();
+    Ok(parse_document(markdown)?.blocks)
+}
+
+/// Parse a markdown document into its runnable blocks and document-wide settings.
+pub fn parse_document(markdown: &str) -> Result<ParsedDocument> {
+    let parser = Parser::new_ext(markdown, Options::all());
+    let mut blocks: Vec<CodeBlock> = Vec::new();
+    let mut settings = DocumentSettings::default();
+    // YAML front matter, collected until its block ends.
+    let mut front_matter: Option<String> = None;
 
     let mut heading_stack: Vec<Heading> = Vec::new();
     let mut active_heading: Option<HeadingBuilder> = None;
     let mut pending_skip: Option<String> = None;
     let mut pending_name: Option<String> = None;
+    let mut pending_mode: Option<ExecMode> = None;
+    let mut pending_session: Option<String> = None;
+    let mut pending_timeout: Option<Duration> = None;
+    let mut pending_tags: Option<Vec<String>> = None;
+    let mut pending_needs: Option<Vec<String>> = None;
+    let mut pending_phase: Option<Phase> = None;
+    let mut pending_expect = false;
+    // Index of the block a `runme:expect` block would bind to: the previous fenced block,
+    // as long as it is runnable.
+    let mut expect_target: Option<usize> = None;
 
     let mut collecting_block = false;
     let mut block_language: Option<String> = None;
     let mut block_inline_name: Option<String> = None;
+    let mut block_inline_mode: Option<ExecMode> = None;
+    let mut block_inline_session: Option<String> = None;
+    let mut block_inline_timeout: Option<Duration> = None;
+    let mut block_inline_tags: Option<Vec<String>> = None;
+    let mut block_inline_needs: Option<Vec<String>> = None;
+    let mut block_inline_phase: Option<Phase> = None;
+    let mut block_inline_expect = false;
+    let mut block_inline_ignore = false;
     let mut block_content = String::new();
+    let mut block_range: Range<usize> = 0..0;
+    let mut block_text: Option<Range<usize>> = None;
 
     let mut idx: usize = 0;
+    let lines = LineIndex::new(markdown);
 
-    while let Some(event) = parser.next() {
+    for (event, range) in parser.into_offset_iter() {
         match event {
             Event::Start(Tag::Heading { .. }) => {
                 active_heading = Some(HeadingBuilder::new());
             }
+            Event::Start(Tag::MetadataBlock(MetadataBlockKind::YamlStyle)) => {
+                front_matter = Some(String::new());
+            }
+            Event::End(TagEnd::MetadataBlock(MetadataBlockKind::YamlStyle)) => {
+                for (key, value) in front_matter_entries(&front_matter.take().unwrap_or_default()) {
+                    match key.as_str() {
+                        "dockerfile" => settings.dockerfile = Some(parse_dockerfile(Some(value))?),
+                        "normalize" => add_normalizations(&mut settings.normalize, &value)?,
+                        other => bail!("unknown runme front matter key '{other}'"),
+                    }
+                }
+            }
             Event::Text(text) => {
-                if let Some(builder) = active_heading.as_mut() {
+                if let Some(yaml) = front_matter.as_mut() {
+                    yaml.push_str(&text);
+                } else if let Some(builder) = active_heading.as_mut() {
                     builder.push(&text);
                 } else if collecting_block {
                     block_content.push_str(&text);
+                    let start = block_text.as_ref().map_or(range.start, |text| text.start);
+                    block_text = Some(start..range.end);
                 }
             }
             Event::Code(text) => {
@@ -88,20 +301,73 @@ pub fn extract_blocks(markdown: &str) -> Result<Vec<CodeBlock>> {
                                 pending_name = Some(name);
                             }
                         }
+                        DirectiveKind::Mode => {
+                            pending_mode = Some(parse_mode(value.as_deref())?);
+                        }
+                        DirectiveKind::Timeout => {
+                            pending_timeout = Some(parse_timeout(value.as_deref())?);
+                        }
+                        DirectiveKind::Tags => {
+                            pending_tags = Some(parse_list(value.as_deref()));
+                        }
+                        DirectiveKind::Needs => {
+                            pending_needs = Some(parse_list(value.as_deref()));
+                        }
+                        DirectiveKind::Setup => pending_phase = Some(Phase::Setup),
+                        DirectiveKind::Teardown => pending_phase = Some(Phase::Teardown),
+                        DirectiveKind::Expect => pending_expect = true,
+                        DirectiveKind::Normalize => {
+                            add_normalizations(
+                                &mut settings.normalize,
+                                value.as_deref().unwrap_or_default(),
+                            )?;
+                        }
+                        DirectiveKind::Dockerfile => {
+                            settings.dockerfile = Some(parse_dockerfile(value)?);
+                        }
+                        DirectiveKind::Session => {
+                            pending_session =
+                                Some(value.unwrap_or_else(|| DEFAULT_SESSION.to_string()));
+                        }
                     }
                 }
             }
             Event::Start(Tag::CodeBlock(kind)) => {
                 collecting_block = true;
                 block_content.clear();
+                block_range = range;
+                block_text = None;
                 block_inline_name = None;
+                block_inline_mode = None;
+                block_inline_session = None;
+                block_inline_timeout = None;
+                block_inline_tags = None;
+                block_inline_needs = None;
+                block_inline_phase = None;
+                block_inline_expect = false;
+                block_inline_ignore = false;
                 block_language = match kind {
                     CodeBlockKind::Fenced(info) => {
                         let meta = parse_fence_meta(&info);
                         block_inline_name = meta.name;
-                        if meta.ignore {
-                            pending_skip = Some("Marked with runme:ignore".to_string());
+                        block_inline_session = meta.session;
+                        block_inline_phase = meta.phase;
+                        block_inline_expect = meta.expect;
+                        if meta.mode.is_some() {
+                            block_inline_mode = Some(parse_mode(meta.mode.as_deref())?);
+                        }
+                        if meta.timeout.is_some() {
+                            block_inline_timeout = Some(parse_timeout(meta.timeout.as_deref())?);
                         }
+                        if meta.tags.is_some() {
+                            block_inline_tags = Some(parse_list(meta.tags.as_deref()));
+                        }
+                        if meta.needs.is_some() {
+                            block_inline_needs = Some(parse_list(meta.needs.as_deref()));
+                        }
+                        // Applied only if this block turns out to be runnable, so an ignored
+                        // output block cannot skip the next command.
+                        block_inline_ignore = meta.ignore;
                         meta.language
                     }
                     CodeBlockKind::Indented => None,
@@ -117,18 +383,89 @@ pub fn extract_blocks(markdown: &str) -> Result<Vec<CodeBlock>> {
                     return Err(anyhow!("encountered closing code block without start"));
                 }
 
+                collecting_block = false;
+                if std::mem::take(&mut pending_expect) || block_inline_expect {
+                    // Output blocks are not runnable; they annotate the block right before them.
+                    let (line, _) = lines.position(block_range.start);
+                    let target = expect_target
+                        .map(|index| &mut blocks[index])
+                        .ok_or_else(|| {
+                            anyhow!(
+                                "runme:expect on line {line} must directly follow a runnable code block"
+                            )
+                        })?;
+                    if target.expected_stdout.is_some() {
+                        bail!(
+                            "runme:expect on line {line}: {} already has an expected output block",
+                            target.id
+                        );
+                    }
+                    target.expected_stdout = Some(block_content.trim_end().to_string());
+                    target.expected_span =
+                        Some(content_span(markdown, &block_range, block_text.take()));
+                    block_language = None;
+                    block_content.clear();
+                    continue;
+                }
+
                 idx += 1;
                 let id = format!("block-{idx:03}");
+                let (line, column) = lines.position(block_range.start);
+                let (end_line, end_column) = lines.position(block_range.end);
+                // `content` is trimmed, so skip the blank lines the trim removed.
+                let content_line = match &block_text {
+                    Some(text) => {
+                        let skipped = block_content.len() - block_content.trim_start().len();
+                        let (text_line, _) = lines.position(text.start);
+                        text_line + block_content[..skipped].matches('\n').count()
+                    }
+                    None => line + 1,
+                };
                 blocks.push(CodeBlock {
                     id,
+                    file: None,
                     name: pending_name.take().or_else(|| block_inline_name.take()),
                     language: block_language.clone(),
                     headings: heading_stack.iter().map(|h| h.title.clone()).collect(),
                     content: block_content.trim().to_string(),
-                    skip_reason: pending_skip.take(),
+                    skip_reason: block_inline_ignore
+                        .then(|| "Marked with runme:ignore".to_string())
+                        .or(pending_skip.take()),
+                    mode: pending_mode.take().or_else(|| block_inline_mode.take()),
+                    session: pending_session
+                        .take()
+                        .or_else(|| block_inline_session.take()),
+                    timeout: pending_timeout
+                        .take()
+                        .or_else(|| block_inline_timeout.take()),
+                    tags: pending_tags
+                        .take()
+                        .or_else(|| block_inline_tags.take())
+                        .unwrap_or_default(),
+                    needs: pending_needs
+                        .take()
+                        .or_else(|| block_inline_needs.take())
+                        .unwrap_or_default(),
+                    phase: pending_phase
+                        .take()
+                        .or_else(|| block_inline_phase.take())
+                        .unwrap_or_default(),
+                    expected_stdout: None,
+                    range: block_range.clone(),
+                    location: SourceLocation {
+                        line,
+                        column,
+                        end_line,
+                        end_column,
+                        content_line,
+                    },
+                    expected_span: None,
                 });
+                expect_target = blocks
+                    .last()
+                    .filter(|block| block.is_shell())
+                    .map(|_| blocks.len() - 1);
 
-                collecting_block = false;
                 block_language = None;
                 block_content.clear();
             }
@@ -138,7 +475,115 @@ pub fn extract_blocks(markdown: &str) -> Result<Vec<CodeBlock>> {
 
     anyhow::ensure!(!collecting_block, "markdown ended while inside code block");
 
-    Ok(blocks)
+    Ok(ParsedDocument { blocks, settings })
+}
+
+/// Append the comma- or space-separated `rules` that `normalize` does not list yet.
+fn add_normalizations(normalize: &mut Vec<Normalization>, rules: &str) -> Result<()> {
+    for raw in rules.split([',', ' ']).filter(|raw| !raw.is_empty()) {
+        let rule = Normalization::parse(raw)?;
+        if !normalize.contains(&rule) {
+            normalize.push(rule);
+        }
+    }
+    Ok(())
+}
+
+fn parse_dockerfile(value: Option<String>) -> Result<PathBuf> {
+    value
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow!("runme:dockerfile expects a path to a Dockerfile"))
+}
+
+/// `key: value` entries of the `runme:` mapping in YAML front matter, e.g.
+///
+/// ```yaml
+/// runme:
+///   dockerfile: ci/Dockerfile
+///   normalize: [strip-ansi, workdir]
+/// ```
+///
+/// Only this flat shape is understood; quotes around values and `[a, b]` brackets are dropped.
+fn front_matter_entries(yaml: &str) -> Vec<(String, String)> {
+    let mut entries = Vec::new();
+    let mut in_runme = false;
+    for line in yaml.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        if !line.starts_with([' ', '\t']) {
+            in_runme = trimmed == "runme:";
+            continue;
+        }
+        if let Some((key, value)) = trimmed.split_once(':').filter(|_| in_runme) {
+            let value = value.trim().trim_matches(['"', '\'']);
+            let value = value
+                .strip_prefix('[')
+                .and_then(|list| list.strip_suffix(']'))
+                .unwrap_or(value);
+            entries.push((key.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    entries
+}
+
+/// Line-aligned span of a code block's contents; `text` covers the Text events, if any.
+fn content_span(markdown: &str, block: &Range<usize>, text: Option<Range<usize>>) -> ContentSpan {
+    let line_start = |offset: usize| markdown[..offset].rfind('\n').map_or(0, |idx| idx + 1);
+    match text {
+        Some(text) => {
+            let start = line_start(text.start);
+            ContentSpan {
+                range: start..text.end,
+                indent: markdown[start..text.start].to_string(),
+            }
+        }
+        None => {
+            // Empty block: insert right before the closing fence line.
+            let body = markdown[block.clone()].trim_end_matches(['\n', '\r']);
+            let closing = line_start(block.start + body.len());
+            let fence_offset = markdown[closing..]
+                .find(['`', '~'])
+                .map_or(closing, |idx| closing + idx);
+            ContentSpan {
+                range: closing..closing,
+                indent: markdown[closing..fence_offset].to_string(),
+            }
+        }
+    }
+}
+
+/// Replace the contents of fenced blocks, keeping every other byte of `markdown` intact.
+pub fn replace_contents(markdown: &str, edits: &[(ContentSpan, String)]) -> String {
+    let mut edits: Vec<&(ContentSpan, String)> = edits.iter().collect();
+    edits.sort_by_key(|(span, _)| span.range.start);
+
+    let mut output = String::with_capacity(markdown.len());
+    let mut cursor = 0;
+    for (span, contents) in edits {
+        output.push_str(&markdown[cursor..span.range.start]);
+        let newline = line_ending(markdown, span);
+        for line in contents.lines() {
+            output.push_str(&span.indent);
+            output.push_str(line);
+            output.push_str(newline);
+        }
+        cursor = span.range.end;
+    }
+    output.push_str(&markdown[cursor..]);
+    output
+}
+
+/// Line ending used by the block's current contents, or by its opening fence when it is empty.
+fn line_ending(markdown: &str, span: &ContentSpan) -> &'static str {
+    let contents = &markdown[span.range.clone()];
+    let crlf = match contents.find('\n') {
+        Some(idx) => contents[..idx].ends_with('\r'),
+        None => markdown[..span.range.start].ends_with("\r\n"),
+    };
+    if crlf { "\r\n" } else { "\n" }
 }
 
 #[derive(Clone, Debug)]
@@ -190,6 +635,13 @@ fn heading_depth(level: HeadingLevel) -> u32 {
 struct FenceMeta {
     language: Option<String>,
     name: Option<String>,
+    mode: Option<String>,
+    session: Option<String>,
+    timeout: Option<String>,
+    tags: Option<String>,
+    needs: Option<String>,
+    phase: Option<Phase>,
+    expect: bool,
     ignore: bool,
 }
 
@@ -204,10 +656,32 @@ fn parse_fence_meta(info: &CowStr) -> FenceMeta {
         let token_lower = token.to_ascii_lowercase();
         if token_lower == "runme:ignore" || token_lower == "runme:skip" {
             meta.ignore = true;
+        } else if token_lower == "runme:expect" {
+            meta.expect = true;
+        } else if token_lower == "output" {
+            // A bare ```output fence is plain text; after a language it is just a marker.
+            meta.expect = true;
+            meta.language.get_or_insert_with(|| "text".to_string());
+        } else if token_lower == "runme:setup" {
+            meta.phase = Some(Phase::Setup);
+        } else if token_lower == "runme:teardown" {
+            meta.phase = Some(Phase::Teardown);
+        } else if token_lower == "runme:session" {
+            meta.session = Some(DEFAULT_SESSION.to_string());
         } else if let Some((key, value)) = token.split_once('=') {
             let key_lower = key.to_ascii_lowercase();
             if key_lower == "runme:name" && !value.is_empty() {
                 meta.name = Some(value.to_string());
+            } else if key_lower == "runme:mode" {
+                meta.mode = Some(value.to_string());
+            } else if key_lower == "runme:session" && !value.is_empty() {
+                meta.session = Some(value.to_string());
+            } else if key_lower == "runme:timeout" {
+                meta.timeout = Some(value.to_string());
+            } else if key_lower == "runme:tags" {
+                meta.tags = Some(value.to_string());
+            } else if key_lower == "runme:needs" {
+                meta.needs = Some(value.to_string());
             }
         } else if meta.language.is_none() {
             meta.language = Some(token.to_ascii_lowercase());
@@ -221,8 +695,19 @@ fn parse_fence_meta(info: &CowStr) -> FenceMeta {
 enum DirectiveKind {
     Ignore,
     Name,
+    Mode,
+    Session,
+    Timeout,
+    Tags,
+    Needs,
+    Setup,
+    Teardown,
+    Expect,
+    Normalize,
+    Dockerfile,
 }
 
+/// Parse `<!-- runme:key value -->` (or `runme:key=value`) comments preceding a block.
 fn parse_directive(html: &CowStr) -> Option<(DirectiveKind, Option<String>)> {
     let raw = html.trim();
     if !(raw.starts_with("<!--") && raw.ends_with("-->")) {
@@ -232,18 +717,83 @@ fn parse_directive(html: &CowStr) -> Option<(DirectiveKind, Option<String>)> {
         .trim_start_matches("<!--")
         .trim_end_matches("-->")
         .trim();
-    let lower = inner.to_ascii_lowercase();
-    if lower.starts_with("runme:ignore") || lower.starts_with("runme:skip") {
-        Some((DirectiveKind::Ignore, None))
-    } else if lower.starts_with("runme:name") {
-        let name = inner[10..].trim();
-        Some((
-            DirectiveKind::Name,
-            (!name.is_empty()).then(|| name.to_string()),
-        ))
-    } else {
-        None
+    let prefix = inner.get(..6)?;
+    if !prefix.eq_ignore_ascii_case("runme:") {
+        return None;
+    }
+    let body = &inner[6..];
+    let key_end = body
+        .find(|ch: char| ch.is_whitespace() || ch == '=')
+        .unwrap_or(body.len());
+    let key = body[..key_end].to_ascii_lowercase();
+    let value = body[key_end..].trim_start_matches('=').trim();
+    let value = (!value.is_empty()).then(|| value.to_string());
+
+    match key.as_str() {
+        "ignore" | "skip" => Some((DirectiveKind::Ignore, None)),
+        "name" => Some((DirectiveKind::Name, value)),
+        "mode" => Some((DirectiveKind::Mode, value)),
+        "session" => Some((DirectiveKind::Session, value)),
+        "timeout" => Some((DirectiveKind::Timeout, value)),
+        "tags" | "tag" => Some((DirectiveKind::Tags, value)),
+        "needs" => Some((DirectiveKind::Needs, value)),
+        "setup" => Some((DirectiveKind::Setup, None)),
+        "teardown" => Some((DirectiveKind::Teardown, None)),
+        "expect" => Some((DirectiveKind::Expect, None)),
+        "normalize" => Some((DirectiveKind::Normalize, value)),
+        "dockerfile" => Some((DirectiveKind::Dockerfile, value)),
+        _ => None,
+    }
+}
+
+fn parse_mode(value: Option<&str>) -> Result<ExecMode> {
+    let raw = value.unwrap_or_default();
+    ExecMode::from_str(raw, true)
+        .map_err(|_| anyhow!("runme:mode expects `lines` or `script`, got '{raw}'"))
+}
+
+fn parse_timeout(value: Option<&str>) -> Result<Duration> {
+    let raw = value.unwrap_or_default();
+    parse_duration(raw).map_err(|err| anyhow!("runme:timeout {err}"))
+}
+
+/// Split `smoke,slow` (or `smoke slow`) into distinct items, as used by `runme:tags`
+/// and `runme:needs`.
+fn parse_list(value: Option<&str>) -> Vec<String> {
+    let mut items: Vec<String> = Vec::new();
+    for item in value.unwrap_or_default().split([',', ' ']) {
+        let item = item.trim();
+        if !item.is_empty() && !items.iter().any(|seen| seen == item) {
+            items.push(item.to_string());
+        }
+    }
+    items
+}
+
+/// Parse durations such as `500ms`, `30s`, `2m` or `1h`; a bare number means seconds.
+pub fn parse_duration(raw: &str) -> Result<Duration, String> {
+    let raw = raw.trim();
+    let split = raw
+        .find(|ch: char| !ch.is_ascii_digit() && ch != '.')
+        .unwrap_or(raw.len());
+    let (number, unit) = raw.split_at(split);
+    let value: f64 = number
+        .parse()
+        .map_err(|_| format!("expects a duration like 30s, 500ms or 2m, got '{raw}'"))?;
+    let seconds = match unit.trim().to_ascii_lowercase().as_str() {
+        "ms" => value / 1000.0,
+        "" | "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        other => return Err(format!("has unknown unit '{other}' in '{raw}'")),
+    };
+    if !seconds.is_finite() {
+        return Err(format!("is too large, got '{raw}'"));
+    }
+    if seconds <= 0.0 {
+        return Err(format!("must be greater than zero, got '{raw}'"));
     }
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("is too large, got '{raw}'"))
 }
 
 #[cfg(test)]
@@ -312,4 +862,316 @@ echo hi
             Some("Marked with runme:ignore")
         );
     }
+
+    #[test]
+    fn captures_mode_directives() {
+        let doc = r#"
+<!-- runme:mode script -->
+```bash
+cd /tmp && ls
+```
+
+```sh runme:mode=lines
+echo plain
+```
+
+```bash
+echo default
+```
+"#;
+        let blocks = extract_blocks(doc).expect("parse");
+        assert_eq!(blocks[0].mode, Some(ExecMode::Script));
+        assert_eq!(blocks[1].mode, Some(ExecMode::Lines));
+        assert_eq!(blocks[2].mode, None);
+
+        let invalid = extract_blocks("<!-- runme:mode turbo -->\n```bash\necho\n```\n");
+        assert!(invalid.is_err(), "unknown modes are rejected");
+    }
+
+    #[test]
+    fn captures_session_directives() {
+        let doc = r#"
+<!-- runme:session tutorial -->
+```bash
+cd demo
+```
+
+```bash runme:session=tutorial
+ls
+```
+
+```bash runme:session
+echo shared
+```
+
+```bash
+echo isolated
+```
+"#;
+        let blocks = extract_blocks(doc).expect("parse");
+        assert_eq!(blocks[0].session.as_deref(), Some("tutorial"));
+        assert_eq!(blocks[1].session.as_deref(), Some("tutorial"));
+        assert_eq!(blocks[2].session.as_deref(), Some(DEFAULT_SESSION));
+        assert_eq!(blocks[3].session, None);
+    }
+
+    #[test]
+    fn captures_tag_and_needs_directives() {
+        let doc = r#"
+<!-- runme:tags smoke, slow,smoke -->
+```bash
+make test
+```
+
+```bash runme:tags=smoke
+curl localhost
+```
+
+```bash
+ls
+```
+"#;
+        let blocks = extract_blocks(doc).expect("parse");
+        assert_eq!(blocks[0].tags, ["smoke", "slow"]);
+        assert_eq!(blocks[1].tags, ["smoke"]);
+        assert!(blocks[2].tags.is_empty());
+
+        let blocks = extract_blocks(
+            "<!-- runme:needs install-deps, build -->\n```bash\nmake\n```\n\n```bash runme:needs=block-001\nls\n```\n",
+        )
+        .expect("parse needs");
+        assert_eq!(blocks[0].needs, ["install-deps", "build"]);
+        assert_eq!(blocks[1].needs, ["block-001"]);
+    }
+
+    #[test]
+    fn captures_setup_and_teardown_phases() {
+        let doc = r#"
+<!-- runme:setup -->
+```bash
+npm ci
+```
+
+```bash
+npm test
+```
+
+```bash runme:teardown
+rm -rf node_modules
+```
+"#;
+        let blocks = extract_blocks(doc).expect("parse");
+        let phases: Vec<Phase> = blocks.iter().map(|block| block.phase).collect();
+        assert_eq!(phases, [Phase::Setup, Phase::Main, Phase::Teardown]);
+    }
+
+    #[test]
+    fn captures_timeout_directives() {
+        let doc = r#"
+<!-- runme:timeout 90s -->
+```bash
+make test
+```
+
+```bash runme:timeout=500ms
+curl localhost
+```
+"#;
+        let blocks = extract_blocks(doc).expect("parse");
+        assert_eq!(blocks[0].timeout, Some(Duration::from_secs(90)));
+        assert_eq!(blocks[1].timeout, Some(Duration::from_millis(500)));
+
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse_duration("5"), Ok(Duration::from_secs(5)));
+        assert!(parse_duration("soon").is_err());
+        assert_eq!(
+            parse_duration("99999999999999999999h"),
+            Err("is too large, got '99999999999999999999h'".to_string())
+        );
+        assert!(parse_duration(&"9".repeat(400)).is_err());
+        assert!(extract_blocks("<!-- runme:timeout 3 days -->\n```bash\nls\n```\n").is_err());
+    }
+
+    #[test]
+    fn binds_expected_output_blocks() {
+        let doc = r#"
+```bash
+echo hello
+```
+
+<!-- runme:expect -->
+```text
+hello
+```
+
+```bash
+printf 'a\nb\n'
+```
+
+```console output
+a
+b
+```
+
+```bash
+echo unbound
+```
+
+```text
+free-standing
+```
+"#;
+        let blocks = extract_blocks(doc).expect("parse");
+        assert_eq!(
+            blocks.len(),
+            4,
+            "bound output blocks are not separate blocks"
+        );
+        assert_eq!(blocks[0].expected_stdout.as_deref(), Some("hello"));
+        assert_eq!(blocks[1].expected_stdout.as_deref(), Some("a\nb"));
+        assert_eq!(blocks[1].id, "block-002");
+        assert_eq!(blocks[2].expected_stdout, None);
+        assert_eq!(blocks[3].language.as_deref(), Some("text"));
+
+        let ignored_output = extract_blocks(
+            "```bash\necho hi\n```\n\n```text output runme:ignore\nhi\n```\n\n```bash\necho next\n```\n",
+        )
+        .expect("parse");
+        assert_eq!(ignored_output.len(), 2);
+        assert_eq!(
+            ignored_output[1].skip_reason, None,
+            "runme:ignore on an output block stays with it"
+        );
+
+        let bare = extract_blocks("```bash\necho hi\n```\n\n```output\nhi\n```\n").expect("parse");
+        assert_eq!(bare.len(), 1);
+        assert_eq!(bare[0].expected_stdout.as_deref(), Some("hi"));
+
+        let orphan = extract_blocks("<!-- runme:expect -->\n```text\nhi\n```\n");
+        assert!(orphan.is_err(), "expectations need a preceding block");
+        let after_data = extract_blocks(
+            "```bash\necho hi\n```\n\n```json\n{}\n```\n\n```text output\nhi\n```\n",
+        );
+        let err = after_data
+            .expect_err("the json block sits in between")
+            .to_string();
+        assert!(err.contains("line 9"), "{err}");
+        let twice = extract_blocks(
+            "```bash\necho hi\n```\n\n```text output\nhi\n```\n\n```text output\nhi\n```\n",
+        );
+        let err = twice.expect_err("one expectation per block").to_string();
+        assert!(err.contains("block-001 already has"), "{err}");
+    }
+
+    #[test]
+    fn normalize_directive_applies_to_whole_document() {
+        let doc = r#"
+```bash
+pwd
+```
+
+<!-- runme:normalize workdir, strip-ansi -->
+<!-- runme:normalize collapse-whitespace -->
+
+```bash
+ls
+```
+"#;
+        let parsed = parse_document(doc).expect("parse");
+        assert_eq!(parsed.blocks.len(), 2);
+        assert_eq!(
+            parsed.settings.normalize,
+            [
+                Normalization::Workdir,
+                Normalization::StripAnsi,
+                Normalization::CollapseWhitespace,
+            ]
+        );
+
+        let front_matter = "---\nrunme:\n  normalize: [strip-ansi, workdir]\n---\n";
+        assert_eq!(
+            parse_document(front_matter)
+                .expect("parse")
+                .settings
+                .normalize,
+            [Normalization::StripAnsi, Normalization::Workdir]
+        );
+
+        let invalid = extract_blocks("<!-- runme:normalize shout -->\n");
+        assert!(invalid.is_err(), "unknown rules are rejected");
+    }
+
+    #[test]
+    fn dockerfile_comes_from_a_directive_or_front_matter() {
+        let doc = "```bash\nmake\n```\n\n<!-- runme:dockerfile ci/Dockerfile -->\n\n```bash\nmake test\n```\n";
+        let parsed = parse_document(doc).expect("parse");
+        assert_eq!(
+            parsed.settings.dockerfile.as_deref(),
+            Some(Path::new("ci/Dockerfile"))
+        );
+        assert!(extract_blocks("<!-- runme:dockerfile -->\n").is_err());
+
+        let doc = "---\ntitle: Guide\nrunme:\n  dockerfile: \"ci/Dockerfile\"\n---\n\n# Guide\n";
+        let parsed = parse_document(doc).expect("parse");
+        assert!(parsed.blocks.is_empty());
+        assert_eq!(
+            parsed.settings.dockerfile.as_deref(),
+            Some(Path::new("ci/Dockerfile"))
+        );
+        let typo = parse_document("---\nrunme:\n  dockerfle: ci/Dockerfile\n---\n");
+        assert!(typo.is_err(), "unknown keys are rejected");
+    }
+
+    #[test]
+    fn rewrites_expected_blocks_byte_for_byte() {
+        let doc = "# Demo\r\n\n```bash\necho new\n```\n\n<!-- runme:expect -->\n```text\nold\nlines\n```\n\n> ```sh\n> echo quoted\n> ```\n>\n> ```text output\n> ```\n\ntrailer  \n";
+        let blocks = extract_blocks(doc).expect("parse");
+        assert_eq!(&doc[blocks[0].range.clone()], "```bash\necho new\n```");
+
+        let first = blocks[0].expected_span.clone().expect("first block bound");
+        assert_eq!(&doc[first.range.clone()], "old\nlines\n");
+        let quoted = blocks[1].expected_span.clone().expect("quoted block bound");
+        assert!(quoted.range.is_empty());
+        assert_eq!(quoted.indent, "> ");
+
+        let updated = replace_contents(doc, &[(quoted, "quoted".into()), (first, "new".into())]);
+        assert_eq!(
+            updated,
+            "# Demo\r\n\n```bash\necho new\n```\n\n<!-- runme:expect -->\n```text\nnew\n```\n\n> ```sh\n> echo quoted\n> ```\n>\n> ```text output\n> quoted\n> ```\n\ntrailer  \n"
+        );
+    }
+
+    #[test]
+    fn rewrites_crlf_blocks_with_crlf() {
+        let doc = "```bash\r\necho new\r\n```\r\n\r\n```text output\r\nold\r\n```\r\n\r\n```bash\r\necho also\r\n```\r\n\r\n```text output\r\n```\r\n";
+        let blocks = extract_blocks(doc).expect("parse");
+        let edits: Vec<_> = blocks
+            .iter()
+            .map(|block| {
+                let span = block.expected_span.clone().expect("bound");
+                (span, "one\ntwo".to_string())
+            })
+            .collect();
+
+        assert_eq!(
+            replace_contents(doc, &edits),
+            "```bash\r\necho new\r\n```\r\n\r\n```text output\r\none\r\ntwo\r\n```\r\n\r\n```bash\r\necho also\r\n```\r\n\r\n```text output\r\none\r\ntwo\r\n```\r\n"
+        );
+    }
+
+    #[test]
+    fn records_source_locations() {
+        let doc = "# Title\n\n```bash\n\necho one\nfalse\n```\n\n- item\n\n  ```sh\n  ls\n  ```\n";
+        let blocks = extract_blocks(doc).expect("parse");
+
+        let first = blocks[0].location;
+        assert_eq!((first.line, first.column), (3, 1));
+        assert_eq!((first.end_line, first.end_column), (7, 4));
+        assert_eq!(first.content_line, 5, "leading blank lines are skipped");
+        assert_eq!(first.source_line(2), 6);
+
+        let nested = blocks[1].location;
+        assert_eq!((nested.line, nested.column), (11, 3));
+        assert_eq!(nested.content_line, 12);
+    }
 }
@@ -0,0 +1,225 @@
+//! GitHub Actions workflow commands (`--format github`).
+//!
+//! Each block's streamed output is folded into a `::group::`, failures become
+//! `::error` annotations pointing at the markdown line, and duplicate
+//! `runme:name`s become `::warning`s, so problems show up inline on the PR diff.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{failure_message, stderr_tail};
+use crate::markdown::CodeBlock;
+use crate::runner::{BlockReport, BlockStatus, ExecEvents};
+
+/// Trailing stderr lines copied into an error annotation.
+const STDERR_TAIL: usize = 5;
+
+/// `::warning` annotation, e.g. for a `runme:name` shared by several blocks.
+pub fn warning(file: Option<&Path>, line: usize, title: &str, message: &str) -> String {
+    annotation("warning", file, line, title, message)
+}
+
+/// `::error` annotation for a failed or timed-out block, or `None` when it did not fail.
+pub fn error(report: &BlockReport) -> Option<String> {
+    if !report.status.is_failure() {
+        return None;
+    }
+    let mut message = failure_message(report);
+    for line in stderr_tail(report, STDERR_TAIL) {
+        message.push('\n');
+        message.push_str(line);
+    }
+    let line = report.failed_source_line.unwrap_or(report.location.line);
+    Some(annotation(
+        "error",
+        report.file.as_deref(),
+        line,
+        &title(&report.id, report.name.as_deref()),
+        &message,
+    ))
+}
+
+fn annotation(level: &str, file: Option<&Path>, line: usize, title: &str, message: &str) -> String {
+    let mut properties = Vec::new();
+    if let Some(file) = file {
+        properties.push(format!(
+            "file={}",
+            escape_property(&file.display().to_string())
+        ));
+        properties.push(format!("line={line}"));
+    }
+    properties.push(format!("title={}", escape_property(title)));
+    format!(
+        "::{level} {}::{}",
+        properties.join(","),
+        escape_data(message)
+    )
+}
+
+fn title(id: &str, name: Option<&str>) -> String {
+    match name {
+        Some(name) => format!("{id} ({name})"),
+        None => id.to_string(),
+    }
+}
+
+/// Workflow command data must encode `%`, CR and LF.
+fn escape_data(text: &str) -> String {
+    text.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Property values additionally encode the `:` and `,` separators.
+fn escape_property(text: &str) -> String {
+    escape_data(text).replace(':', "%3A").replace(',', "%2C")
+}
+
+/// Streams block output inside collapsible groups and annotates failures as blocks finish.
+pub struct GithubStream<W: Write> {
+    out: W,
+    /// Output is wrapped in `::stop-commands::` so commands printed by blocks are not obeyed;
+    /// each group gets a fresh token.
+    token: String,
+}
+
+impl<W: Write> GithubStream<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            token: String::new(),
+        }
+    }
+}
+
+/// Unguessable `::stop-commands::` token, so block output cannot print it to resume commands.
+///
+/// `RandomState` keys its hasher from OS randomness, which keeps the digest of otherwise
+/// predictable inputs secret without pulling in an RNG crate.
+fn fresh_token() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    let [high, low] = [0u8, 1].map(|half| {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u8(half);
+        hasher.write_u64(count);
+        hasher.write_u128(nanos);
+        hasher.write_u32(std::process::id());
+        hasher.finish()
+    });
+    format!("runme-{high:016x}{low:016x}")
+}
+
+impl<W: Write> ExecEvents for GithubStream<W> {
+    fn block_started(&mut self, block: &CodeBlock) {
+        let location = match &block.file {
+            Some(file) => format!(" {}:{}", file.display(), block.location.line),
+            None => String::new(),
+        };
+        let title = title(&block.id, block.name.as_deref());
+        let _ = writeln!(self.out, "::group::{title}{location}");
+        self.token = fresh_token();
+        let _ = writeln!(self.out, "::stop-commands::{}", self.token);
+    }
+
+    fn line_started(&mut self, _block: &CodeBlock, _line: Option<usize>, command: &str) {
+        let _ = writeln!(self.out, "$ {command}");
+    }
+
+    fn stdout(&mut self, _block: &CodeBlock, _line: Option<usize>, chunk: &str) {
+        let _ = writeln!(self.out, "{chunk}");
+    }
+
+    fn stderr(&mut self, _block: &CodeBlock, _line: Option<usize>, chunk: &str) {
+        let _ = writeln!(self.out, "{chunk}");
+    }
+
+    fn block_finished(&mut self, _block: &CodeBlock, report: &BlockReport) {
+        let _ = writeln!(self.out, "::{}::", self.token);
+        if let BlockStatus::Skipped = report.status {
+            let reason = report.skip_reason.as_deref().unwrap_or_default();
+            let _ = writeln!(self.out, "skipped: {reason}");
+        }
+        let _ = writeln!(self.out, "::endgroup::");
+        if let Some(error) = error(report) {
+            let _ = writeln!(self.out, "{error}");
+        }
+        let _ = self.out.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markdown::extract_blocks;
+    use crate::report::fixtures::report;
+    use crate::runner::{ExecOptions, HostSandbox, SessionPool, execute};
+
+    #[test]
+    fn annotations_point_at_the_failing_markdown_line() {
+        let mut failed = report(
+            "docs/a,b.md",
+            "block-005",
+            BlockStatus::Failed { exit_code: Some(2) },
+        );
+        failed.name = Some("build: all".into());
+        assert_eq!(
+            error(&failed).expect("failures are annotated"),
+            "::error file=docs/a%2Cb.md,line=12,title=block-005 (build%3A all)::exit code 2 at line 12%0A$ make <build>%0Amake: *** no rule & stop"
+        );
+        assert!(error(&report("README.md", "block-001", BlockStatus::Passed)).is_none());
+
+        assert_eq!(
+            warning(
+                Some(Path::new("README.md")),
+                42,
+                "duplicate runme:name",
+                "100% used twice"
+            ),
+            "::warning file=README.md,line=42,title=duplicate runme%3Aname::100%25 used twice"
+        );
+    }
+
+    #[test]
+    fn groups_streamed_output_and_disables_commands_inside() {
+        let mut blocks =
+            extract_blocks("```bash runme:name=greet\necho '::error::fake'\nfalse\n```\n")
+                .expect("parse");
+        blocks[0].file = Some("README.md".into());
+
+        let mut stream = GithubStream::new(Vec::new());
+        execute(
+            &blocks[0],
+            &mut HostSandbox::new("."),
+            &mut SessionPool::default(),
+            &ExecOptions::default(),
+            &mut stream,
+        )
+        .expect("block runs");
+
+        let token = stream.token.clone();
+        assert!(token.starts_with("runme-") && token.len() == 38, "{token}");
+        assert_ne!(fresh_token(), token, "every group gets its own token");
+        assert_eq!(
+            String::from_utf8(stream.out)
+                .unwrap()
+                .replace(&token, "tok"),
+            "::group::block-001 (greet) README.md:1\n\
+             ::stop-commands::tok\n\
+             $ echo '::error::fake'\n\
+             ::error::fake\n\
+             $ false\n\
+             ::tok::\n\
+             ::endgroup::\n\
+             ::error file=README.md,line=3,title=block-001 (greet)::exit code 1 at line 3\n"
+        );
+    }
+}
@@ -0,0 +1,279 @@
+//! Standalone HTML report (`--format html`) for doc reviews.
+//!
+//! The page renders each markdown file and decorates every executed block
+//! with a status badge, its duration, and collapsible captured output. A
+//! summary table at the top links to the blocks. Styles are inlined and raw
+//! HTML from the docs is shown as text, so the page runs no scripts and can be
+//! archived or attached as-is.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use pulldown_cmark::{CowStr, Event, Options, Parser, Tag, TagEnd, html};
+
+use super::{Summary, escape_markup, failure_message};
+use crate::markdown;
+use crate::runner::{BlockReport, BlockStatus};
+
+const STYLE: &str = "
+body { font-family: system-ui, sans-serif; max-width: 960px; margin: 2rem auto; padding: 0 1rem; color: #1f2328; }
+pre { background: #f6f8fa; padding: .75rem; overflow-x: auto; border-radius: 6px; }
+table { border-collapse: collapse; width: 100%; }
+th, td { text-align: left; padding: .3rem .6rem; border-bottom: 1px solid #d0d7de; }
+.doc { border-top: 3px solid #d0d7de; margin-top: 2rem; }
+.block { border: 1px solid #d0d7de; border-left-width: 6px; border-radius: 6px; padding: .5rem .75rem; margin: 1rem 0; }
+.block.passed { border-left-color: #1a7f37; }
+.block.failed, .block.timed-out { border-left-color: #cf222e; }
+.block.skipped { border-left-color: #9a6700; }
+.badge { display: inline-block; border-radius: 1em; padding: 0 .6em; color: #fff; font-size: .85em; font-weight: 600; }
+.badge.passed { background: #1a7f37; }
+.badge.failed, .badge.timed-out { background: #cf222e; }
+.badge.skipped { background: #9a6700; }
+.meta { color: #59636e; font-size: .9em; }
+";
+
+/// A markdown file to render, with the source text its blocks were read from.
+pub struct Page<'a> {
+    pub path: &'a Path,
+    pub markdown: &'a str,
+}
+
+/// Render the whole report as one HTML document.
+pub fn render(pages: &[Page], reports: &[BlockReport]) -> Result<String> {
+    let summary = Summary::of(reports);
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n");
+    out.push_str("<title>runme report</title>\n");
+    let _ = writeln!(out, "<style>{STYLE}</style>\n</head>\n<body>");
+    out.push_str("<h1>runme report</h1>\n");
+    let _ = writeln!(
+        out,
+        "<p class=\"meta\">{} block(s): {} passed, {} failed, {} timed out, {} skipped in {} ms</p>",
+        summary.total,
+        summary.passed,
+        summary.failed,
+        summary.timed_out,
+        summary.skipped,
+        summary.duration_ms
+    );
+    render_summary_table(&mut out, reports);
+
+    for page in pages {
+        let by_id: HashMap<&str, &BlockReport> = reports
+            .iter()
+            .filter(|report| report.file.as_deref() == Some(page.path))
+            .map(|report| (report.id.as_str(), report))
+            .collect();
+        let _ = writeln!(
+            out,
+            "<section class=\"doc\">\n<h2 class=\"meta\">{}</h2>",
+            escape_markup(&page.path.display().to_string())
+        );
+        render_page(&mut out, page, &by_id)
+            .with_context(|| format!("while rendering {}", page.path.display()))?;
+        out.push_str("</section>\n");
+    }
+    out.push_str("</body>\n</html>\n");
+    Ok(out)
+}
+
+fn render_summary_table(out: &mut String, reports: &[BlockReport]) {
+    out.push_str("<table>\n<thead><tr><th>File</th><th>Block</th><th>Name</th><th>Status</th><th>Duration</th></tr></thead>\n<tbody>\n");
+    for report in reports {
+        let file = report
+            .file
+            .as_deref()
+            .map(|file| file.display().to_string())
+            .unwrap_or_default();
+        let (class, label) = status_badge(&report.status);
+        let _ = writeln!(
+            out,
+            "<tr><td>{}</td><td><a href=\"#{}\">{}</a></td><td>{}</td><td><span class=\"badge {class}\">{label}</span></td><td>{} ms</td></tr>",
+            escape_markup(&file),
+            anchor(report.file.as_deref(), &report.id),
+            escape_markup(&report.id),
+            escape_markup(report.name.as_deref().unwrap_or_default()),
+            report.duration_ms
+        );
+    }
+    out.push_str("</tbody>\n</table>\n");
+}
+
+/// Render the markdown, wrapping each reported code block in its result card.
+fn render_page(out: &mut String, page: &Page, reports: &HashMap<&str, &BlockReport>) -> Result<()> {
+    // Map fence offsets to reports; re-extracting keeps ids aligned with this exact source.
+    let by_offset: HashMap<usize, &BlockReport> = markdown::extract_blocks(page.markdown)?
+        .iter()
+        .filter_map(|block| Some((block.range.start, *reports.get(block.id.as_str())?)))
+        .collect();
+
+    let mut events = Vec::new();
+    let mut open: Option<&BlockReport> = None;
+    for (event, range) in Parser::new_ext(page.markdown, Options::all()).into_offset_iter() {
+        match &event {
+            Event::Start(Tag::CodeBlock(_)) => {
+                if let Some(report) = by_offset.get(&range.start) {
+                    events.push(Event::Html(CowStr::from(block_header(page.path, report))));
+                    open = Some(report);
+                }
+                events.push(event);
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                events.push(event);
+                if let Some(report) = open.take() {
+                    events.push(Event::Html(CowStr::from(block_footer(report))));
+                }
+            }
+            // Docs may embed `<script>` or event handlers; escape them instead of running them.
+            Event::Html(raw) | Event::InlineHtml(raw) => events.push(Event::Text(raw.clone())),
+            _ => events.push(event),
+        }
+    }
+    html::push_html(out, events.into_iter());
+    Ok(())
+}
+
+fn block_header(path: &Path, report: &BlockReport) -> String {
+    let (class, label) = status_badge(&report.status);
+    let mut header = format!(
+        "<div class=\"block {class}\" id=\"{}\">\n<div><span class=\"badge {class}\">{label}</span> <code>{}</code>",
+        anchor(Some(path), &report.id),
+        escape_markup(&report.id)
+    );
+    if let Some(name) = &report.name {
+        let _ = write!(header, " <strong>{}</strong>", escape_markup(name));
+    }
+    let _ = write!(
+        header,
+        " <span class=\"meta\">line {} · {} ms",
+        report.location.line, report.duration_ms
+    );
+    if let Some(sandbox) = &report.sandbox {
+        let _ = write!(header, " · {}", escape_markup(sandbox));
+    }
+    header.push_str("</span></div>\n");
+    header
+}
+
+fn block_footer(report: &BlockReport) -> String {
+    let mut footer = String::new();
+    if report.status.is_failure() {
+        let _ = writeln!(
+            footer,
+            "<p><strong>{}</strong></p>",
+            escape_markup(&failure_message(report))
+        );
+    }
+    if let Some(reason) = &report.skip_reason {
+        let _ = writeln!(
+            footer,
+            "<p class=\"meta\">skipped: {}</p>",
+            escape_markup(reason)
+        );
+    }
+    // Failures open their output by default; passing blocks keep it folded away.
+    let open = if report.status.is_failure() {
+        " open"
+    } else {
+        ""
+    };
+    for (label, text) in [
+        ("expected output diff", &report.output_diff),
+        ("stdout", &report.stdout),
+        ("stderr", &report.stderr),
+    ] {
+        if let Some(text) = text.as_deref().filter(|text| !text.is_empty()) {
+            let _ = writeln!(
+                footer,
+                "<details{open}><summary>{label}</summary><pre>{}</pre></details>",
+                escape_markup(text)
+            );
+        }
+    }
+    footer.push_str("</div>\n");
+    footer
+}
+
+fn status_badge(status: &BlockStatus) -> (&'static str, &'static str) {
+    match status {
+        BlockStatus::Passed => ("passed", "passed"),
+        BlockStatus::Failed { .. } => ("failed", "failed"),
+        BlockStatus::TimedOut { .. } => ("timed-out", "timed out"),
+        BlockStatus::Skipped => ("skipped", "skipped"),
+    }
+}
+
+/// Fragment id unique across files, e.g. `docs-guide-md-block-002`.
+fn anchor(file: Option<&Path>, id: &str) -> String {
+    let file = file
+        .map(|file| file.display().to_string())
+        .unwrap_or_default();
+    format!("{file}-{id}")
+        .chars()
+        .map(|ch| if ch.is_ascii_alphanumeric() { ch } else { '-' })
+        .collect::<String>()
+        .trim_start_matches('-')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::fixtures::report;
+
+    #[test]
+    fn decorates_executed_blocks_inside_rendered_markdown() {
+        let markdown = "# Guide\n\n- step one:\n\n  ```bash\n  make <all>\n  ```\n\n```text runme:expect\nok\n```\n\n```bash\nls\n```\n";
+        let mut failed = report(
+            "docs/guide.md",
+            "block-001",
+            BlockStatus::Failed { exit_code: Some(2) },
+        );
+        failed.location.line = 5;
+        let reports = vec![failed];
+        let pages = [Page {
+            path: Path::new("docs/guide.md"),
+            markdown,
+        }];
+
+        let html = render(&pages, &reports).expect("render");
+        assert!(html.contains("1 block(s): 0 passed, 1 failed, 0 timed out, 0 skipped"));
+        assert!(html.contains("<a href=\"#docs-guide-md-block-001\">block-001</a>"));
+        assert!(html.contains("<h1>Guide</h1>"));
+
+        let card = html
+            .find("<div class=\"block failed\" id=\"docs-guide-md-block-001\">")
+            .expect("failed block is decorated");
+        let code = html.find("make &lt;all&gt;").expect("code is rendered");
+        let details = html
+            .find("<details open><summary>stderr</summary><pre>$ make &lt;build&gt;")
+            .expect("stderr is attached");
+        assert!(card < code && code < details, "card wraps the code block");
+        assert!(html.contains("<p><strong>exit code 2 at line 12</strong></p>"));
+        assert_eq!(
+            html.matches("class=\"block ").count(),
+            1,
+            "expect blocks and unreported blocks stay plain"
+        );
+    }
+
+    #[test]
+    fn escapes_raw_html_from_the_docs() {
+        let markdown =
+            "Hi <script>alert(1)</script> there.\n\n<div onclick=\"steal()\">block</div>\n";
+        let pages = [Page {
+            path: Path::new("guide.md"),
+            markdown,
+        }];
+
+        let html = render(&pages, &[]).expect("render");
+        assert!(!html.contains("<script"), "{html}");
+        assert!(!html.contains("<div onclick"), "{html}");
+        assert!(
+            html.contains("&lt;script&gt;alert(1)&lt;/script&gt;"),
+            "{html}"
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! Closing summary for the human format.
+//!
+//! Long runs scroll individual block reports out of view, so the run ends with
+//! the counts, wall time, the slowest blocks, and a recap of every failure.
+
+use std::cmp::Reverse;
+use std::fmt::Write;
+use std::time::Duration;
+
+use super::{Summary, failure_message, source_ref, stderr_tail};
+use crate::markdown::Phase;
+use crate::runner::{BlockReport, BlockStatus};
+
+/// Trailing stderr lines repeated under each failure.
+const STDERR_TAIL: usize = 5;
+
+/// Render the summary, listing up to `slowest` of the longest-running blocks.
+///
+/// Setup and teardown blocks are counted on their own lines, apart from the regular blocks.
+pub fn summary(reports: &[BlockReport], wall: Duration, slowest: usize) -> String {
+    let in_phase = |phase: Phase| reports.iter().filter(move |report| report.phase == phase);
+    let mut out = String::from("\n== summary ==\n");
+    let _ = writeln!(
+        out,
+        "{} in {:.2}s",
+        counts(Summary::of(in_phase(Phase::Main))),
+        wall.as_secs_f64()
+    );
+    for (label, phase) in [("setup", Phase::Setup), ("teardown", Phase::Teardown)] {
+        let summary = Summary::of(in_phase(phase));
+        if summary.total > 0 {
+            let _ = writeln!(out, "{label}: {}", counts(summary));
+        }
+    }
+
+    let mut by_duration: Vec<&BlockReport> = reports
+        .iter()
+        .filter(|report| !matches!(report.status, BlockStatus::Skipped))
+        .collect();
+    // Stable sort keeps document order among equally fast blocks.
+    by_duration.sort_by_key(|report| Reverse(report.duration_ms));
+    by_duration.truncate(slowest);
+    if !by_duration.is_empty() {
+        out.push_str("slowest:\n");
+        for report in by_duration {
+            let _ = writeln!(
+                out,
+                "  {:>8} ms  {}",
+                report.duration_ms,
+                describe(report, report.location.line)
+            );
+        }
+    }
+
+    let failures: Vec<&BlockReport> = reports
+        .iter()
+        .filter(|report| report.status.is_failure())
+        .collect();
+    if !failures.is_empty() {
+        out.push_str("failures:\n");
+        for report in failures {
+            let line = report.failed_source_line.unwrap_or(report.location.line);
+            let _ = writeln!(
+                out,
+                "  {}: {}",
+                describe(report, line),
+                failure_message(report)
+            );
+            for line in stderr_tail(report, STDERR_TAIL) {
+                let _ = writeln!(out, "      {line}");
+            }
+        }
+    }
+    out
+}
+
+fn counts(summary: Summary) -> String {
+    format!(
+        "{} block(s): {} passed, {} failed, {} timed out, {} skipped",
+        summary.total, summary.passed, summary.failed, summary.timed_out, summary.skipped
+    )
+}
+
+/// `README.md:12 block-002 (build)`.
+fn describe(report: &BlockReport, line: usize) -> String {
+    let location = source_ref(report.file.as_deref(), line);
+    match &report.name {
+        Some(name) => format!("{location} {} ({name})", report.id),
+        None => format!("{location} {}", report.id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::fixtures::report;
+
+    #[test]
+    fn recaps_counts_slowest_blocks_and_failures() {
+        let mut fast = report("README.md", "block-001", BlockStatus::Passed);
+        fast.duration_ms = 40;
+        let mut failed = report(
+            "docs/guide.md",
+            "block-002",
+            BlockStatus::Failed { exit_code: Some(2) },
+        );
+        failed.name = Some("build".into());
+        let skipped = report("README.md", "block-003", BlockStatus::Skipped);
+        let mut setup = report("README.md", "block-000", BlockStatus::Passed);
+        setup.phase = Phase::Setup;
+        setup.duration_ms = 10;
+
+        let text = summary(
+            &[setup, fast, failed, skipped],
+            Duration::from_millis(3210),
+            1,
+        );
+        assert_eq!(
+            text,
+            "\n== summary ==\n\
+             3 block(s): 1 passed, 1 failed, 0 timed out, 1 skipped in 3.21s\n\
+             setup: 1 block(s): 1 passed, 0 failed, 0 timed out, 0 skipped\n\
+             slowest:\n\
+             \x20     1250 ms  docs/guide.md:10 block-002 (build)\n\
+             failures:\n\
+             \x20 docs/guide.md:12 block-002 (build): exit code 2 at line 12\n\
+             \x20     $ make <build>\n\
+             \x20     make: *** no rule & stop\n"
+        );
+    }
+}
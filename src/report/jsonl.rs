@@ -0,0 +1,276 @@
+//! JSON Lines event stream (`--format jsonl`) for editors and dashboards.
+//!
+//! Every line is one JSON object with an `event` tag and `elapsed_ms` since
+//! the run started. Events arrive in this order:
+//!
+//! - `run_started`: `schema_version`, `runme_version`, `files`, `blocks` (selected count);
+//! - per block, `block_started` (`file`, `id`, `name`, `source_line`), then per command
+//!   `line_started` (`line`, `source_line`, `command`), any number of `stdout`/`stderr`
+//!   (`line`, `chunk`), and `line_finished` (`line`, `exit_code`, `duration_ms`);
+//! - `block_finished`: every `BlockReport` field, as in `--format json`;
+//! - `run_finished`: `summary` counts and the run's `duration_ms`.
+//!
+//! `line` is the 1-based line within the block and is `null` when a script's
+//! output cannot be attributed to one. [`SCHEMA_VERSION`] only changes when
+//! fields are removed or change meaning; new events and fields may be added
+//! at any time, so consumers should ignore what they do not recognise.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use super::Summary;
+use crate::markdown::CodeBlock;
+use crate::runner::{BlockReport, ExecEvents};
+
+/// Version of the event schema described in the module docs.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event<'a> {
+    RunStarted {
+        schema_version: u32,
+        runme_version: &'static str,
+        files: &'a [PathBuf],
+        blocks: usize,
+    },
+    BlockStarted {
+        #[serde(flatten)]
+        block: BlockRef<'a>,
+        name: Option<&'a str>,
+        source_line: usize,
+    },
+    LineStarted {
+        #[serde(flatten)]
+        block: BlockRef<'a>,
+        line: Option<usize>,
+        source_line: Option<usize>,
+        command: &'a str,
+    },
+    Stdout {
+        #[serde(flatten)]
+        block: BlockRef<'a>,
+        line: Option<usize>,
+        chunk: &'a str,
+    },
+    Stderr {
+        #[serde(flatten)]
+        block: BlockRef<'a>,
+        line: Option<usize>,
+        chunk: &'a str,
+    },
+    LineFinished {
+        #[serde(flatten)]
+        block: BlockRef<'a>,
+        line: Option<usize>,
+        exit_code: Option<i32>,
+        duration_ms: u128,
+    },
+    BlockFinished {
+        #[serde(flatten)]
+        report: &'a BlockReport,
+    },
+    RunFinished {
+        summary: Summary,
+        duration_ms: u128,
+    },
+}
+
+/// Identifies the block an event belongs to.
+#[derive(Serialize)]
+struct BlockRef<'a> {
+    file: Option<&'a Path>,
+    id: &'a str,
+}
+
+impl<'a> BlockRef<'a> {
+    fn new(block: &'a CodeBlock) -> Self {
+        Self {
+            file: block.file.as_deref(),
+            id: &block.id,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    #[serde(flatten)]
+    event: Event<'a>,
+    elapsed_ms: u128,
+}
+
+/// Writes one JSON object per event to `out`.
+pub struct JsonlStream<W: Write> {
+    out: W,
+    started: Instant,
+}
+
+impl<W: Write> JsonlStream<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            started: Instant::now(),
+        }
+    }
+
+    fn emit(&mut self, event: Event) {
+        let record = Record {
+            event,
+            elapsed_ms: self.started.elapsed().as_millis(),
+        };
+        // Progress output is best effort; a closed pipe must not abort the run.
+        if let Ok(line) = serde_json::to_string(&record) {
+            let _ = writeln!(self.out, "{line}");
+            let _ = self.out.flush();
+        }
+    }
+}
+
+impl<W: Write> ExecEvents for JsonlStream<W> {
+    fn run_started(&mut self, files: &[PathBuf], blocks: usize) {
+        self.started = Instant::now();
+        self.emit(Event::RunStarted {
+            schema_version: SCHEMA_VERSION,
+            runme_version: env!("CARGO_PKG_VERSION"),
+            files,
+            blocks,
+        });
+    }
+
+    fn block_started(&mut self, block: &CodeBlock) {
+        self.emit(Event::BlockStarted {
+            block: BlockRef::new(block),
+            name: block.name.as_deref(),
+            source_line: block.location.line,
+        });
+    }
+
+    fn line_started(&mut self, block: &CodeBlock, line: Option<usize>, command: &str) {
+        self.emit(Event::LineStarted {
+            block: BlockRef::new(block),
+            line,
+            source_line: line.map(|line| block.location.source_line(line)),
+            command,
+        });
+    }
+
+    fn stdout(&mut self, block: &CodeBlock, line: Option<usize>, chunk: &str) {
+        self.emit(Event::Stdout {
+            block: BlockRef::new(block),
+            line,
+            chunk,
+        });
+    }
+
+    fn stderr(&mut self, block: &CodeBlock, line: Option<usize>, chunk: &str) {
+        self.emit(Event::Stderr {
+            block: BlockRef::new(block),
+            line,
+            chunk,
+        });
+    }
+
+    fn line_finished(
+        &mut self,
+        block: &CodeBlock,
+        line: Option<usize>,
+        exit_code: Option<i32>,
+        duration: Duration,
+    ) {
+        self.emit(Event::LineFinished {
+            block: BlockRef::new(block),
+            line,
+            exit_code,
+            duration_ms: duration.as_millis(),
+        });
+    }
+
+    fn block_finished(&mut self, _block: &CodeBlock, report: &BlockReport) {
+        self.emit(Event::BlockFinished { report });
+    }
+
+    fn run_finished(&mut self, reports: &[BlockReport], duration: Duration) {
+        self.emit(Event::RunFinished {
+            summary: Summary::of(reports),
+            duration_ms: duration.as_millis(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markdown::{ExecMode, extract_blocks};
+    use crate::runner::{ExecOptions, HostSandbox, SessionPool, execute};
+    use serde_json::Value;
+
+    #[test]
+    fn streams_events_in_order_for_lines_and_scripts() {
+        // Script stdout only follows its line marker reliably once the line has been running a while.
+        let doc = "```bash\necho one\nfalse\n```\n\n```bash runme:mode=script\nsleep 0.2; echo two\n```\n";
+        let blocks = extract_blocks(doc).expect("parse");
+
+        let mut stream = JsonlStream::new(Vec::new());
+        let mut sandbox = HostSandbox::new(".");
+        let mut sessions = SessionPool::default();
+        stream.run_started(&[PathBuf::from("guide.md")], blocks.len());
+        let reports: Vec<_> = blocks
+            .iter()
+            .map(|block| {
+                execute(
+                    block,
+                    &mut sandbox,
+                    &mut sessions,
+                    &ExecOptions::default(),
+                    &mut stream,
+                )
+                .expect("block runs")
+            })
+            .collect();
+        stream.run_finished(&reports, Duration::from_millis(5));
+        assert_eq!(blocks[1].mode, Some(ExecMode::Script));
+
+        let events: Vec<Value> = String::from_utf8(stream.out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("each line is JSON"))
+            .collect();
+        let kinds: Vec<&str> = events
+            .iter()
+            .map(|event| event["event"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                "run_started",
+                "block_started",
+                "line_started",
+                "stdout",
+                "line_finished",
+                "line_started",
+                "line_finished",
+                "block_finished",
+                "block_started",
+                "line_started",
+                "stdout",
+                "line_finished",
+                "block_finished",
+                "run_finished",
+            ]
+        );
+
+        assert_eq!(events[0]["schema_version"], SCHEMA_VERSION);
+        assert_eq!(events[3]["chunk"], "one");
+        assert_eq!(events[5]["command"], "false");
+        assert_eq!(events[5]["source_line"], 3);
+        assert_eq!(events[6]["exit_code"], 1);
+        assert_eq!(events[7]["id"], "block-001");
+        assert_eq!(events[10]["line"], 1, "script output keeps its line");
+        assert_eq!(events[11]["exit_code"], 0);
+        assert_eq!(events[13]["summary"]["failed"], 1);
+        assert_eq!(events[13]["summary"]["passed"], 1);
+    }
+}
@@ -0,0 +1,183 @@
+//! JUnit XML output (`--format junit`) for CI servers such as Jenkins and GitLab.
+//!
+//! Every markdown file becomes a `<testsuite>` and every block a `<testcase>`
+//! named after its `runme:name` (or id), with the heading path as classname.
+
+use std::fmt::Write;
+use std::path::Path;
+
+use super::{Summary, by_file, escape_markup, failure_message};
+use crate::runner::{BlockReport, BlockStatus};
+
+/// Render all reports as one `<testsuites>` document.
+pub fn render(reports: &[BlockReport]) -> String {
+    let groups = by_file(reports);
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let totals = Summary::of(reports);
+    let _ = writeln!(
+        xml,
+        "<testsuites name=\"runme\" tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"{}\" time=\"{}\">",
+        totals.total,
+        totals.failures(),
+        totals.skipped,
+        seconds(totals.duration_ms)
+    );
+    for (file, members) in groups {
+        render_suite(&mut xml, file, &members);
+    }
+    xml.push_str("</testsuites>\n");
+    xml
+}
+
+fn render_suite(xml: &mut String, file: Option<&Path>, reports: &[&BlockReport]) {
+    let suite = file.map_or_else(|| "runme".to_string(), |file| file.display().to_string());
+    let totals = Summary::of(reports.iter().copied());
+    let _ = writeln!(
+        xml,
+        "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"{}\" time=\"{}\">",
+        escape_markup(&suite),
+        totals.total,
+        totals.failures(),
+        totals.skipped,
+        seconds(totals.duration_ms)
+    );
+
+    for report in reports {
+        let name = report.name.as_deref().unwrap_or(&report.id);
+        // Some consumers reject an empty classname, so root-level blocks use the suite name.
+        let classname = if report.headings.is_empty() {
+            suite.clone()
+        } else {
+            report.headings.join(" › ")
+        };
+        let _ = write!(
+            xml,
+            "    <testcase name=\"{}\" classname=\"{}\" time=\"{}\"",
+            escape_markup(name),
+            escape_markup(&classname),
+            seconds(report.duration_ms)
+        );
+        if let Some(file) = file {
+            let _ = write!(
+                xml,
+                " file=\"{}\" line=\"{}\"",
+                escape_markup(&file.display().to_string()),
+                report.location.line
+            );
+        }
+        xml.push_str(">\n");
+
+        match &report.status {
+            BlockStatus::Passed => {}
+            BlockStatus::Skipped => {
+                let reason = report.skip_reason.as_deref().unwrap_or("skipped");
+                let _ = writeln!(
+                    xml,
+                    "      <skipped message=\"{}\"/>",
+                    escape_markup(reason)
+                );
+            }
+            BlockStatus::Failed { .. } | BlockStatus::TimedOut { .. } => {
+                let _ = writeln!(
+                    xml,
+                    "      <failure message=\"{}\" type=\"{}\">{}</failure>",
+                    escape_markup(&failure_message(report)),
+                    failure_type(&report.status),
+                    escape_markup(&failure_body(report))
+                );
+            }
+        }
+        if let Some(stdout) = report.stdout.as_deref().filter(|out| !out.is_empty()) {
+            let _ = writeln!(
+                xml,
+                "      <system-out>{}</system-out>",
+                escape_markup(stdout)
+            );
+        }
+        if let Some(stderr) = report.stderr.as_deref().filter(|err| !err.is_empty()) {
+            let _ = writeln!(
+                xml,
+                "      <system-err>{}</system-err>",
+                escape_markup(stderr)
+            );
+        }
+        xml.push_str("    </testcase>\n");
+    }
+    xml.push_str("  </testsuite>\n");
+}
+
+fn failure_type(status: &BlockStatus) -> &'static str {
+    match status {
+        BlockStatus::TimedOut { .. } => "timeout",
+        _ => "failure",
+    }
+}
+
+/// Captured stderr, plus the expected-output diff when that is why the block failed.
+fn failure_body(report: &BlockReport) -> String {
+    let mut body = report.stderr.clone().unwrap_or_default();
+    if let Some(diff) = &report.output_diff {
+        body.push_str(diff);
+    }
+    body
+}
+
+fn seconds(duration_ms: u128) -> String {
+    format!("{:.3}", duration_ms as f64 / 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::fixtures::report;
+
+    #[test]
+    fn maps_files_to_suites_and_blocks_to_cases() {
+        let mut named = report("README.md", "block-001", BlockStatus::Passed);
+        named.name = Some("install".into());
+        let reports = vec![
+            named,
+            report("docs/guide.md", "block-001", BlockStatus::Skipped),
+            report(
+                "README.md",
+                "block-002",
+                BlockStatus::Failed { exit_code: Some(2) },
+            ),
+        ];
+
+        let xml = render(&reports);
+        assert!(xml.contains(
+            "<testsuites name=\"runme\" tests=\"3\" failures=\"1\" errors=\"0\" skipped=\"1\" time=\"3.750\">"
+        ));
+        let readme = xml
+            .find("<testsuite name=\"README.md\" tests=\"2\"")
+            .unwrap();
+        let guide = xml
+            .find("<testsuite name=\"docs/guide.md\" tests=\"1\"")
+            .unwrap();
+        assert!(readme < guide, "suites keep discovery order");
+        assert!(xml.contains(
+            "<testcase name=\"install\" classname=\"Guide › Install\" time=\"1.250\" file=\"README.md\" line=\"10\">"
+        ));
+        assert!(xml.contains("<skipped message=\"Marked with runme:ignore\"/>"));
+        assert!(xml.contains(
+            "<failure message=\"exit code 2 at line 12\" type=\"failure\">$ make &lt;build&gt;\nmake: *** no rule &amp; stop\n</failure>"
+        ));
+    }
+
+    #[test]
+    fn escapes_control_characters_and_reports_timeouts() {
+        let mut timed_out = report(
+            "README.md",
+            "block-003",
+            BlockStatus::TimedOut { timeout_ms: 500 },
+        );
+        timed_out.stdout = Some("\x1b[32mgreen\x1b[0m \"quoted\"".into());
+
+        let xml = render(&[timed_out]);
+        assert!(
+            xml.contains("<failure message=\"timed out after 500ms at line 12\" type=\"timeout\">")
+        );
+        assert!(xml.contains("<system-out>green &quot;quoted&quot;</system-out>"));
+    }
+}
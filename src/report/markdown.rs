@@ -0,0 +1,198 @@
+//! Markdown summary (`--format markdown`) for PR comments and `$GITHUB_STEP_SUMMARY`.
+//!
+//! A compact results table is followed by a `<details>` section per failing
+//! block holding its source and captured output.
+
+use std::fmt::Write;
+
+use super::{Summary, failure_message};
+use crate::markdown::CodeBlock;
+use crate::runner::{BlockReport, BlockStatus, strip_ansi};
+
+/// Render the summary; `blocks` supplies the source of failing blocks.
+pub fn render(reports: &[BlockReport], blocks: &[&CodeBlock]) -> String {
+    let summary = Summary::of(reports);
+    let mut out = String::from("## runme results\n\n");
+    let _ = writeln!(
+        out,
+        "**{} passed, {} failed, {} timed out, {} skipped** in {:.2}s\n",
+        summary.passed,
+        summary.failed,
+        summary.timed_out,
+        summary.skipped,
+        summary.duration_ms as f64 / 1000.0
+    );
+
+    out.push_str("| Block | Name | Headings | Status | Duration |\n");
+    out.push_str("| --- | --- | --- | --- | --- |\n");
+    for report in reports {
+        let _ = writeln!(
+            out,
+            "| `{}` | {} | {} | {} | {} ms |",
+            cell(&label(report)),
+            cell(report.name.as_deref().unwrap_or_default()),
+            cell(&report.headings.join(" › ")),
+            status(&report.status),
+            report.duration_ms
+        );
+    }
+
+    for report in reports.iter().filter(|report| report.status.is_failure()) {
+        let block = blocks
+            .iter()
+            .find(|block| block.id == report.id && block.file == report.file);
+        push_failure(&mut out, report, block.copied());
+    }
+    out
+}
+
+fn push_failure(out: &mut String, report: &BlockReport, block: Option<&CodeBlock>) {
+    let title = match &report.name {
+        Some(name) => format!("{} ({name})", label(report)),
+        None => label(report),
+    };
+    let _ = writeln!(
+        out,
+        "\n<details>\n<summary>{} <code>{}</code>: {}</summary>\n",
+        status(&report.status),
+        html_escape(&title),
+        html_escape(&failure_message(report))
+    );
+    if let Some(block) = block {
+        let language = block.language.as_deref().unwrap_or_default();
+        push_fenced(out, "Source", language, &block.content);
+    }
+    push_fenced(
+        out,
+        "Expected output diff",
+        "diff",
+        report.output_diff.as_deref().unwrap_or_default(),
+    );
+    push_fenced(
+        out,
+        "stdout",
+        "text",
+        report.stdout.as_deref().unwrap_or_default(),
+    );
+    push_fenced(
+        out,
+        "stderr",
+        "text",
+        report.stderr.as_deref().unwrap_or_default(),
+    );
+    out.push_str("</details>\n");
+}
+
+/// Fenced block long enough that backticks inside `text` cannot close it early.
+fn push_fenced(out: &mut String, heading: &str, language: &str, text: &str) {
+    if text.trim().is_empty() {
+        return;
+    }
+    let longest_run = text
+        .split(|ch| ch != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    let text = strip_ansi(text);
+    let _ = writeln!(
+        out,
+        "{heading}:\n\n{fence}{language}\n{}\n{fence}\n",
+        text.trim_end()
+    );
+}
+
+fn label(report: &BlockReport) -> String {
+    match &report.file {
+        Some(file) => format!("{}#{}", file.display(), report.id),
+        None => report.id.clone(),
+    }
+}
+
+fn status(status: &BlockStatus) -> &'static str {
+    match status {
+        BlockStatus::Passed => "✅ passed",
+        BlockStatus::Failed { .. } => "❌ failed",
+        BlockStatus::TimedOut { .. } => "⏱️ timed out",
+        BlockStatus::Skipped => "⏭️ skipped",
+    }
+}
+
+/// Table cells cannot contain pipes or line breaks, so pipes are escaped and each run of line
+/// breaks becomes a single space. GFM honors `\|` inside code spans in tables too.
+fn cell(text: &str) -> String {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace('|', "\\|")
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markdown::extract_blocks;
+    use crate::report::fixtures::report;
+
+    #[test]
+    fn tabulates_results_and_details_failures() {
+        let mut blocks =
+            extract_blocks("```bash\necho ok\n```\n\n```bash\nmake <build> | tee ```log```\n```\n")
+                .expect("parse");
+        for block in &mut blocks {
+            block.file = Some("README.md".into());
+        }
+        let mut passed = report("README.md", "block-001", BlockStatus::Passed);
+        passed.name = Some("a|b".into());
+        let failed = report(
+            "README.md",
+            "block-002",
+            BlockStatus::Failed { exit_code: Some(2) },
+        );
+
+        let blocks: Vec<&CodeBlock> = blocks.iter().collect();
+        let summary = render(&[passed, failed], &blocks);
+        assert!(summary.contains("**1 passed, 1 failed, 0 timed out, 0 skipped** in 2.50s"));
+        assert!(
+            summary.contains(
+                "| `README.md#block-001` | a\\|b | Guide › Install | ✅ passed | 1250 ms |"
+            )
+        );
+        assert!(
+            summary
+                .contains("| `README.md#block-002` |  | Guide › Install | ❌ failed | 1250 ms |")
+        );
+        assert_eq!(summary.matches("<details>").count(), 1);
+        assert!(summary.contains(
+            "<summary>❌ failed <code>README.md#block-002</code>: exit code 2 at line 12</summary>"
+        ));
+        assert!(summary.contains("Source:\n\n````bash\nmake <build> | tee ```log```\n````\n"));
+        assert!(
+            summary.contains("stderr:\n\n```text\n$ make <build>\nmake: *** no rule & stop\n```\n")
+        );
+    }
+
+    #[test]
+    fn escapes_pipes_and_line_breaks_in_table_cells() {
+        let mut odd = report("docs/a|b.md", "block-001", BlockStatus::Passed);
+        odd.name = Some("build | test\r\n\nthen ship".into());
+        odd.headings = vec!["Install\nsteps".into(), "x|y".into()];
+
+        let summary = render(&[odd], &[]);
+        let row = summary
+            .lines()
+            .find(|line| line.contains("block-001"))
+            .expect("table row");
+        assert_eq!(
+            row,
+            "| `docs/a\\|b.md#block-001` | build \\| test then ship | Install steps › x\\|y | ✅ passed | 1250 ms |"
+        );
+    }
+}
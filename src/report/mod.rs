@@ -0,0 +1,163 @@
+//! Renderers that turn `BlockReport`s into machine-readable formats and the
+//! human end-of-run summary.
+//!
+//! Each format lives in its own module. Document formats return the rendered
+//! text so the CLI decides where it goes; streaming formats implement
+//! `ExecEvents` and print as blocks run.
+
+pub mod github;
+pub mod html;
+pub mod human;
+pub mod jsonl;
+pub mod junit;
+pub mod markdown;
+pub mod tap;
+
+use std::fmt::Write;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::runner::{BlockReport, BlockStatus, strip_ansi};
+
+/// Block counts by outcome plus the summed block durations.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Summary {
+    pub total: usize,
+    pub passed: usize,
+    pub failed: usize,
+    pub timed_out: usize,
+    pub skipped: usize,
+    pub duration_ms: u128,
+}
+
+impl Summary {
+    pub fn of<'a>(reports: impl IntoIterator<Item = &'a BlockReport>) -> Self {
+        let mut summary = Self::default();
+        for report in reports {
+            summary.total += 1;
+            summary.duration_ms += report.duration_ms;
+            match report.status {
+                BlockStatus::Passed => summary.passed += 1,
+                BlockStatus::Failed { .. } => summary.failed += 1,
+                BlockStatus::TimedOut { .. } => summary.timed_out += 1,
+                BlockStatus::Skipped => summary.skipped += 1,
+            }
+        }
+        summary
+    }
+
+    /// Blocks that count against the run.
+    pub fn failures(&self) -> usize {
+        self.failed + self.timed_out
+    }
+}
+
+/// Group reports by markdown file, keeping the order files were first seen.
+fn by_file(reports: &[BlockReport]) -> Vec<(Option<&Path>, Vec<&BlockReport>)> {
+    let mut groups: Vec<(Option<&Path>, Vec<&BlockReport>)> = Vec::new();
+    for report in reports {
+        let file = report.file.as_deref();
+        match groups.iter_mut().find(|(seen, _)| *seen == file) {
+            Some((_, members)) => members.push(report),
+            None => groups.push((file, vec![report])),
+        }
+    }
+    groups
+}
+
+/// One-line reason a failed or timed-out block failed, e.g. `exit code 2 at line 12`.
+fn failure_message(report: &BlockReport) -> String {
+    let mut message = match report.status {
+        BlockStatus::TimedOut { timeout_ms } => format!("timed out after {timeout_ms}ms"),
+        BlockStatus::Failed { .. } if report.output_diff.is_some() => {
+            "stdout differs from runme:expect block".to_string()
+        }
+        BlockStatus::Failed {
+            exit_code: Some(code),
+        } => format!("exit code {code}"),
+        _ => "terminated by signal".to_string(),
+    };
+    if let Some(line) = report.failed_source_line {
+        let _ = write!(message, " at line {line}");
+    }
+    message
+}
+
+/// The last `count` lines of a block's captured stderr.
+fn stderr_tail(report: &BlockReport, count: usize) -> Vec<&str> {
+    let lines: Vec<&str> = report
+        .stderr
+        .as_deref()
+        .unwrap_or_default()
+        .lines()
+        .collect();
+    lines[lines.len().saturating_sub(count)..].to_vec()
+}
+
+/// `README.md:42`-style reference, or `line 42` for blocks without a file.
+pub fn source_ref(file: Option<&Path>, line: usize) -> String {
+    match file {
+        Some(file) => format!("{}:{line}", file.display()),
+        None => format!("line {line}"),
+    }
+}
+
+/// Escape XML/HTML markup characters and drop ANSI colors and other control characters that
+/// XML 1.0 cannot carry.
+fn escape_markup(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in strip_ansi(text).chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(ch),
+            ch if ch.is_control() => {}
+            ch => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use std::path::PathBuf;
+
+    use crate::markdown::{Phase, SourceLocation};
+    use crate::runner::{BlockReport, BlockStatus};
+
+    /// Report for `id` in `file` with realistic metadata around the given status.
+    pub fn report(file: &str, id: &str, status: BlockStatus) -> BlockReport {
+        let failed = status.is_failure();
+        BlockReport {
+            file: Some(PathBuf::from(file)),
+            id: id.into(),
+            location: SourceLocation {
+                line: 10,
+                column: 1,
+                end_line: 13,
+                end_column: 4,
+                content_line: 11,
+            },
+            name: None,
+            headings: vec!["Guide".into(), "Install".into()],
+            language: Some("bash".into()),
+            sandbox: Some("host".into()),
+            session: None,
+            phase: Phase::Main,
+            duration_ms: 1250,
+            skip_reason: matches!(status, BlockStatus::Skipped)
+                .then(|| "Marked with runme:ignore".into()),
+            failed_line: failed.then_some(2),
+            failed_source_line: failed.then_some(12),
+            output_diff: None,
+            updated_expectation: None,
+            stdout: Some("$ echo ok\nok\n".into()),
+            stderr: failed.then(|| "$ make <build>\nmake: *** no rule & stop\n".into()),
+            status,
+        }
+    }
+}
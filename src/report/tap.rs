@@ -0,0 +1,165 @@
+//! Test Anything Protocol output (`--format tap`) for prove harnesses and TAP consumers.
+//!
+//! The plan is printed up front, test points are emitted as each block
+//! finishes, and failures carry a YAML diagnostics block with the captured output.
+//! A runme error ends the stream with `Bail out!` instead of the remaining points.
+
+use std::fmt::Write;
+use std::io;
+use std::path::PathBuf;
+
+use super::failure_message;
+use crate::markdown::CodeBlock;
+use crate::runner::{BlockReport, BlockStatus, ExecEvents, strip_ansi};
+
+/// Version line; TAP 13 is the first version that defines YAML diagnostics.
+pub const HEADER: &str = "TAP version 13\n";
+
+/// `ok N - README.md:12 block-003 (name)` line for the `number`-th block, with diagnostics on
+/// failure. The location keeps ids that repeat across files apart.
+pub fn test_point(number: usize, report: &BlockReport) -> String {
+    let mut description = match &report.file {
+        Some(file) => format!("{}:{} {}", file.display(), report.location.line, report.id),
+        None => report.id.clone(),
+    };
+    if let Some(name) = &report.name {
+        let _ = write!(description, " ({name})");
+    }
+    // `#` starts a directive in TAP, so it cannot appear unescaped in the description.
+    let description = description.replace('#', "\\#");
+
+    let mut out = String::new();
+    match &report.status {
+        BlockStatus::Passed => {
+            let _ = writeln!(out, "ok {number} - {description}");
+        }
+        BlockStatus::Skipped => {
+            let reason = report.skip_reason.as_deref().unwrap_or_default();
+            let _ = writeln!(out, "ok {number} - {description} # SKIP {reason}");
+        }
+        BlockStatus::Failed { .. } | BlockStatus::TimedOut { .. } => {
+            let _ = writeln!(out, "not ok {number} - {description}");
+            push_diagnostics(&mut out, report);
+        }
+    }
+    out
+}
+
+/// Plan line announcing `total` test points.
+pub fn plan(total: usize) -> String {
+    format!("1..{total}\n")
+}
+
+/// `Bail out!` line telling the harness the run stopped early, with `reason` on one line.
+pub fn bail_out(reason: &str) -> String {
+    format!(
+        "Bail out! {}\n",
+        reason.split_whitespace().collect::<Vec<_>>().join(" ")
+    )
+}
+
+/// Writes the header and plan when the run starts and a test point per finished block.
+pub struct TapStream<W: io::Write> {
+    out: W,
+    finished: usize,
+}
+
+impl<W: io::Write> TapStream<W> {
+    pub fn new(out: W) -> Self {
+        Self { out, finished: 0 }
+    }
+}
+
+impl<W: io::Write> ExecEvents for TapStream<W> {
+    fn run_started(&mut self, _files: &[PathBuf], blocks: usize) {
+        let _ = write!(self.out, "{HEADER}{}", plan(blocks));
+        let _ = self.out.flush();
+    }
+
+    fn block_finished(&mut self, _block: &CodeBlock, report: &BlockReport) {
+        self.finished += 1;
+        let _ = write!(self.out, "{}", test_point(self.finished, report));
+        let _ = self.out.flush();
+    }
+
+    fn run_aborted(&mut self, error: &anyhow::Error) {
+        let _ = write!(self.out, "{}", bail_out(&format!("{error:#}")));
+        let _ = self.out.flush();
+    }
+}
+
+fn push_diagnostics(out: &mut String, report: &BlockReport) {
+    out.push_str("  ---\n");
+    let _ = writeln!(out, "  message: {}", quote(&failure_message(report)));
+    out.push_str("  severity: fail\n");
+    if let Some(file) = &report.file {
+        let line = report.failed_source_line.unwrap_or(report.location.line);
+        let _ = writeln!(
+            out,
+            "  at: {}",
+            quote(&format!("{}:{line}", file.display()))
+        );
+    }
+    if let BlockStatus::Failed {
+        exit_code: Some(code),
+    } = report.status
+    {
+        let _ = writeln!(out, "  exit_code: {code}");
+    }
+    push_literal(out, "diff", report.output_diff.as_deref());
+    push_literal(out, "stdout", report.stdout.as_deref());
+    push_literal(out, "stderr", report.stderr.as_deref());
+    out.push_str("  ...\n");
+}
+
+/// YAML literal block scalar, which keeps captured output verbatim without escaping.
+fn push_literal(out: &mut String, key: &str, text: Option<&str>) {
+    let Some(text) = text.filter(|text| !text.trim().is_empty()) else {
+        return;
+    };
+    let _ = writeln!(out, "  {key}: |");
+    for line in strip_ansi(text).lines() {
+        let _ = writeln!(out, "    {line}");
+    }
+}
+
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::fixtures::report;
+
+    #[test]
+    fn emits_ok_skip_and_not_ok_test_points() {
+        let mut passed = report("README.md", "block-001", BlockStatus::Passed);
+        passed.name = Some("install #1".into());
+        assert_eq!(
+            test_point(1, &passed),
+            "ok 1 - README.md:10 block-001 (install \\#1)\n"
+        );
+
+        let skipped = report("README.md", "block-002", BlockStatus::Skipped);
+        assert_eq!(
+            test_point(2, &skipped),
+            "ok 2 - README.md:10 block-002 # SKIP Marked with runme:ignore\n"
+        );
+
+        let failed = report(
+            "README.md",
+            "block-003",
+            BlockStatus::Failed { exit_code: Some(2) },
+        );
+        assert_eq!(
+            test_point(3, &failed),
+            "not ok 3 - README.md:10 block-003\n  ---\n  message: 'exit code 2 at line 12'\n  severity: fail\n  at: 'README.md:12'\n  exit_code: 2\n  stdout: |\n    $ echo ok\n    ok\n  stderr: |\n    $ make <build>\n    make: *** no rule & stop\n  ...\n"
+        );
+        assert_eq!(plan(3), "1..3\n");
+        assert_eq!(
+            bail_out("while running block-004 in README.md:\n  no such file"),
+            "Bail out! while running block-004 in README.md: no such file\n"
+        );
+    }
+}
@@ -1,26 +1,105 @@
 use std::env;
-use std::ffi::OsString;
-use std::path::PathBuf;
-use std::process::Command;
-use std::time::Instant;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::sync::{Mutex, Once};
+use std::time::{Duration, Instant};
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
+use sha2::{Digest, Sha256};
 
-use super::sandbox::{CommandStatus, OutputSink, Sandbox, spawn_with_streaming};
+use super::sandbox::{CommandStatus, OutputSink, Sandbox, Session, spawn_with_streaming};
+use super::session::ShellSession;
 
-/// Docker sandbox that runs each line inside a disposable container.
+/// Containers started by any sandbox that have not been removed yet, so Ctrl-C can clean up.
+static LIVE_CONTAINERS: Mutex<Vec<(ContainerRuntime, String)>> = Mutex::new(Vec::new());
+static INTERRUPT_HANDLER: Once = Once::new();
+
+/// Container CLI driving the sandbox: `docker`, `podman`, `nerdctl`, or a path to any of
+/// them (or a compatible wrapper). All of them accept Docker's command-line flags.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContainerRuntime {
+    program: PathBuf,
+}
+
+impl ContainerRuntime {
+    pub fn new(program: impl Into<PathBuf>) -> Self {
+        Self {
+            program: program.into(),
+        }
+    }
+
+    /// Runtime name used in labels and messages, e.g. `podman` for `/usr/bin/podman`.
+    pub fn name(&self) -> &str {
+        self.program
+            .file_name()
+            .and_then(OsStr::to_str)
+            .unwrap_or("container")
+    }
+
+    fn is_podman(&self) -> bool {
+        self.name().starts_with("podman")
+    }
+
+    fn command(&self) -> Command {
+        Command::new(&self.program)
+    }
+
+    /// Flags `run` needs on this runtime: rootless Podman maps the invoking user into the
+    /// container so files written to the workspace keep their owner. Rootful Podman rejects
+    /// that mapping, and a `--userns` among the user's own flags wins.
+    fn run_flags(&self, rootless: bool, extra_args: &[String]) -> &'static [&'static str] {
+        let user_chose = extra_args.iter().any(|arg| arg.starts_with("--userns"));
+        if self.is_podman() && rootless && !user_chose {
+            &["--userns=keep-id"]
+        } else {
+            &[]
+        }
+    }
+
+    fn missing_hint(&self) -> String {
+        format!(
+            "while invoking {}; is it installed and on PATH?",
+            self.program.display()
+        )
+    }
+}
+
+impl Default for ContainerRuntime {
+    fn default() -> Self {
+        Self::new("docker")
+    }
+}
+
+/// Container sandbox that keeps one long-lived container per document.
+///
+/// The container starts on the first command with `<runtime> run -d`, every line then runs
+/// through `<runtime> exec`, and the container is removed when the sandbox is dropped (also
+/// when runme bails out with an error) or when runme is interrupted with Ctrl-C. Sessions
+/// get a container of their own.
 ///
 /// Environment variables:
 /// - `RUNME_DOCKER_IMAGE`: override the base image (default `ubuntu:22.04`).
 pub struct DockerSandbox {
+    runtime: ContainerRuntime,
     mount_dir: PathBuf,
     image: String,
+    /// `runtime:image` with the tag separator flattened, e.g. `podman:ubuntu-22.04`.
+    label: String,
     extra_args: Vec<String>,
+    /// Containers started so far; keeps generated container names unique.
+    started: usize,
+    /// Container shared by the lines of this document, once started.
+    container: Option<String>,
+    /// Session containers, removed together with the sandbox.
+    session_containers: Vec<String>,
 }
 
 impl DockerSandbox {
     pub fn new(
         workdir: impl Into<PathBuf>,
+        runtime: ContainerRuntime,
         image: Option<String>,
         extra_args: Vec<String>,
     ) -> Self {
@@ -29,49 +108,348 @@ impl DockerSandbox {
         let image = image
             .or_else(|| env::var("RUNME_DOCKER_IMAGE").ok())
             .unwrap_or_else(|| "ubuntu:22.04".to_string());
+        let label = format!("{}:{}", runtime.name(), image.replace(':', "-"));
         Self {
+            runtime,
             mount_dir,
             image,
+            label,
             extra_args,
+            started: 0,
+            container: None,
+            session_containers: Vec::new(),
         }
     }
 
-    #[cfg(test)]
-    pub(crate) fn image(&self) -> &str {
-        &self.image
+    /// Unique name so the container can be addressed by `exec` and `rm`.
+    fn next_container_name(&mut self) -> String {
+        self.started += 1;
+        format!("runme-{}-{}", process::id(), self.started)
     }
 
-    #[cfg(test)]
-    pub(crate) fn extra_args(&self) -> &[String] {
-        &self.extra_args
-    }
-}
-
-impl Sandbox for DockerSandbox {
-    fn label(&self) -> &str {
-        "docker"
-    }
-
-    fn run(&mut self, argv: &[String], sink: &mut dyn OutputSink) -> Result<CommandStatus> {
+    /// `run -d` invocation that keeps an idle container around for `exec`; the entrypoint is
+    /// replaced so images with their own `ENTRYPOINT` still just wait.
+    fn start_command(&self, name: &str) -> Command {
         let mut volume_spec = OsString::new();
         volume_spec.push(&self.mount_dir);
         volume_spec.push(":");
         volume_spec.push("/workspace");
 
-        let mut cmd = Command::new("docker");
+        let mut cmd = self.runtime.command();
         cmd.arg("run")
+            .arg("-d")
             .arg("--rm")
             .arg("--network=none")
+            .arg("--name")
+            .arg(name)
             .arg("-v")
             .arg(&volume_spec)
             .arg("-w")
             .arg("/workspace")
+            .args(["--entrypoint", "sleep"])
+            .args(self.runtime.run_flags(is_rootless(), &self.extra_args))
             .args(&self.extra_args)
             .arg(&self.image)
-            .args(argv);
+            .arg("infinity");
+        cmd
+    }
+
+    /// `exec` invocation for `container`, minus the command; `interactive` keeps stdin open.
+    fn exec_command(&self, container: &str, interactive: bool) -> Command {
+        let mut cmd = self.runtime.command();
+        cmd.arg("exec");
+        if interactive {
+            cmd.arg("-i");
+        }
+        cmd.arg("-w").arg("/workspace").arg(container);
+        cmd
+    }
+
+    /// Start a fresh container, registered for cleanup before it exists so a Ctrl-C during
+    /// `run -d` does not leak it.
+    fn start_container(&mut self) -> Result<String> {
+        install_interrupt_handler();
+        let name = self.next_container_name();
+        LIVE_CONTAINERS
+            .lock()
+            .unwrap()
+            .push((self.runtime.clone(), name.clone()));
+        let output = self
+            .start_command(&name)
+            .stdin(Stdio::null())
+            .output()
+            .with_context(|| self.runtime.missing_hint());
+        let stderr = match output {
+            Ok(output) if output.status.success() => return Ok(name),
+            Ok(output) => output.stderr,
+            Err(err) => {
+                forget_container(&name);
+                return Err(err);
+            }
+        };
+        remove_container(&self.runtime, &name);
+        bail!(
+            "{} could not start a {} container: {}",
+            self.runtime.name(),
+            self.image,
+            String::from_utf8_lossy(&stderr).trim()
+        );
+    }
+
+    /// The document's shared container, started on first use.
+    fn container(&mut self) -> Result<String> {
+        if let Some(name) = &self.container {
+            return Ok(name.clone());
+        }
+        let name = self.start_container()?;
+        self.container = Some(name.clone());
+        Ok(name)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn image(&self) -> &str {
+        &self.image
+    }
+
+    #[cfg(test)]
+    pub(crate) fn extra_args(&self) -> &[String] {
+        &self.extra_args
+    }
+}
 
+impl Sandbox for DockerSandbox {
+    fn label(&self) -> &str {
+        &self.label
+    }
+
+    fn run(
+        &mut self,
+        argv: &[String],
+        timeout: Option<Duration>,
+        sink: &mut dyn OutputSink,
+    ) -> Result<CommandStatus> {
         let start = Instant::now();
-        let status = spawn_with_streaming(cmd, sink).context("while invoking docker")?;
+        let container = self.container()?;
+        let mut cmd = self.exec_command(&container, false);
+        cmd.args(argv);
+
+        let status = spawn_with_streaming(cmd, timeout, sink)
+            .with_context(|| format!("while invoking {}", self.runtime.name()))?;
+        if status.timed_out {
+            // Killing the CLI leaves the command running in the container; remove it instead.
+            remove_container(&self.runtime, &container);
+            self.container = None;
+            sink.on_stderr(
+                "runme: removed the container after the timeout; later commands start a fresh one",
+            );
+        }
         Ok(status.with_duration(start.elapsed()))
     }
+
+    fn workdir(&self) -> Option<PathBuf> {
+        Some(PathBuf::from("/workspace"))
+    }
+
+    fn open_session(&mut self) -> Result<Box<dyn Session>> {
+        let container = self.start_container()?;
+        self.session_containers.push(container.clone());
+        let mut cmd = self.exec_command(&container, true);
+        cmd.arg("bash");
+        Ok(Box::new(
+            ShellSession::spawn(cmd)
+                .with_context(|| format!("while starting {} session", self.runtime.name()))?
+                .with_kill_command(remove_command(&self.runtime, &container)),
+        ))
+    }
+}
+
+impl Drop for DockerSandbox {
+    fn drop(&mut self) {
+        for container in self.container.iter().chain(&self.session_containers) {
+            remove_container(&self.runtime, container);
+        }
+    }
+}
+
+/// Build `dockerfile` (with its directory as the build context) unless an image built from
+/// the same Dockerfile contents already exists, and return the image tag.
+///
+/// Tags are `runme-sandbox:<hash>` where `<hash>` covers the Dockerfile bytes only, so edit
+/// the Dockerfile (or remove the image) to pick up changes to files it copies in.
+pub fn build_image(runtime: &ContainerRuntime, dockerfile: &Path) -> Result<String> {
+    let contents =
+        fs::read(dockerfile).with_context(|| format!("while reading {}", dockerfile.display()))?;
+    let tag = image_tag(&contents);
+    let cached = runtime
+        .command()
+        .args(["image", "inspect", &tag])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .with_context(|| runtime.missing_hint())?;
+    if cached.success() {
+        return Ok(tag);
+    }
+
+    let context_dir = dockerfile
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    eprintln!("runme: building {tag} from {}", dockerfile.display());
+    // Quiet builds keep stdout free for reports; errors still reach the terminal on stderr.
+    let status = runtime
+        .command()
+        .args(["build", "--quiet", "--tag", &tag, "--file"])
+        .arg(dockerfile)
+        .arg(context_dir)
+        .stdout(Stdio::null())
+        .status()
+        .with_context(|| format!("while invoking {} build", runtime.name()))?;
+    if !status.success() {
+        bail!(
+            "{} build failed for {}",
+            runtime.name(),
+            dockerfile.display()
+        );
+    }
+    Ok(tag)
+}
+
+/// Whether runme runs as an unprivileged user, which makes Podman rootless.
+fn is_rootless() -> bool {
+    // SAFETY: geteuid cannot fail.
+    unsafe { libc::geteuid() != 0 }
+}
+
+/// Image tag derived from the Dockerfile contents.
+fn image_tag(contents: &[u8]) -> String {
+    let digest = format!("{:x}", Sha256::digest(contents));
+    format!("runme-sandbox:{}", &digest[..16])
+}
+
+/// On Ctrl-C, remove every container still running before exiting like an interrupted shell.
+fn install_interrupt_handler() {
+    INTERRUPT_HANDLER.call_once(|| {
+        let installed = ctrlc::set_handler(|| {
+            let live = LIVE_CONTAINERS
+                .lock()
+                .map(|live| live.clone())
+                .unwrap_or_default();
+            for (runtime, container) in &live {
+                let _ = remove_command(runtime, container).status();
+            }
+            process::exit(130);
+        });
+        if let Err(err) = installed {
+            eprintln!("runme: cannot clean up containers on Ctrl-C: {err}");
+        }
+    });
+}
+
+/// Remove `container` (stopping it first) and forget it; already-removed containers are fine.
+fn remove_container(runtime: &ContainerRuntime, container: &str) {
+    let _ = remove_command(runtime, container).status();
+    forget_container(container);
+}
+
+/// Drop `container` from the Ctrl-C cleanup list.
+fn forget_container(container: &str) {
+    if let Ok(mut live) = LIVE_CONTAINERS.lock() {
+        live.retain(|(_, name)| name != container);
+    }
+}
+
+fn remove_command(runtime: &ContainerRuntime, container: &str) -> Command {
+    let mut cmd = runtime.command();
+    cmd.args(["rm", "-f", container])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    cmd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(cmd: &Command) -> Vec<String> {
+        cmd.get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn starts_an_idle_container_and_execs_lines_into_it() {
+        let sandbox = DockerSandbox::new(
+            "/tmp",
+            ContainerRuntime::default(),
+            Some("alpine:3".into()),
+            vec!["--env=FOO=bar".into()],
+        );
+        let start = args(&sandbox.start_command("runme-1-1"));
+        assert_eq!(&start[..3], ["run", "-d", "--rm"]);
+        assert!(start.contains(&"--network=none".to_string()));
+        assert_eq!(
+            &start[start.len() - 5..],
+            [
+                "--entrypoint",
+                "sleep",
+                "--env=FOO=bar",
+                "alpine:3",
+                "infinity"
+            ]
+        );
+
+        let mut exec = sandbox.exec_command("runme-1-1", false);
+        exec.args(["echo", "hi"]);
+        assert_eq!(
+            args(&exec),
+            ["exec", "-w", "/workspace", "runme-1-1", "echo", "hi"]
+        );
+    }
+
+    #[test]
+    fn image_tags_follow_dockerfile_contents() {
+        let tag = image_tag(b"FROM rust:1.85\n");
+        assert_eq!(tag, image_tag(b"FROM rust:1.85\n"));
+        assert_ne!(tag, image_tag(b"FROM rust:1.86\n"));
+        assert!(tag.starts_with("runme-sandbox:"));
+        assert_eq!(tag.len(), "runme-sandbox:".len() + 16);
+    }
+
+    #[test]
+    fn podman_keeps_the_user_and_labels_name_the_runtime() {
+        let podman = DockerSandbox::new(
+            "/tmp",
+            ContainerRuntime::new("/usr/bin/podman"),
+            Some("ubuntu:22.04".into()),
+            Vec::new(),
+        );
+        assert_eq!(podman.label(), "podman:ubuntu-22.04");
+        let start = podman.start_command("runme-1-1");
+        assert_eq!(start.get_program(), "/usr/bin/podman");
+        assert_eq!(
+            args(&start).contains(&"--userns=keep-id".to_string()),
+            is_rootless()
+        );
+        let runtime = ContainerRuntime::new("/usr/bin/podman");
+        assert_eq!(runtime.run_flags(true, &[]), ["--userns=keep-id"]);
+        assert!(runtime.run_flags(false, &[]).is_empty(), "rootful podman");
+        assert!(
+            runtime
+                .run_flags(true, &["--userns=host".to_string()])
+                .is_empty(),
+            "the user's --userns wins"
+        );
+
+        let nerdctl = DockerSandbox::new(
+            "/tmp",
+            ContainerRuntime::new("nerdctl"),
+            Some("alpine:3".into()),
+            Vec::new(),
+        );
+        assert_eq!(nerdctl.label(), "nerdctl:alpine-3");
+        assert!(
+            !args(&nerdctl.start_command("runme-1-1")).contains(&"--userns=keep-id".to_string())
+        );
+    }
 }
@@ -0,0 +1,158 @@
+//! Progress callbacks fired while documents run.
+//!
+//! The runner reports block and line boundaries plus every output chunk as it
+//! arrives; reporters that stream (live terminal output, TAP, JSON Lines)
+//! implement [`ExecEvents`] and ignore whatever they do not need.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::BlockReport;
+use crate::markdown::CodeBlock;
+
+/// Observer for a run. Every method defaults to doing nothing.
+pub trait ExecEvents {
+    /// Blocks were selected and are about to run, across `files`.
+    fn run_started(&mut self, _files: &[PathBuf], _blocks: usize) {}
+
+    fn block_started(&mut self, _block: &CodeBlock) {}
+
+    /// A command starts: `line` is its 1-based line in the block, `None` when a script's
+    /// output cannot be attributed to a line.
+    fn line_started(&mut self, _block: &CodeBlock, _line: Option<usize>, _command: &str) {}
+
+    fn stdout(&mut self, _block: &CodeBlock, _line: Option<usize>, _chunk: &str) {}
+
+    fn stderr(&mut self, _block: &CodeBlock, _line: Option<usize>, _chunk: &str) {}
+
+    /// A command finished. Lines of a script that kept running report no exit code.
+    fn line_finished(
+        &mut self,
+        _block: &CodeBlock,
+        _line: Option<usize>,
+        _exit_code: Option<i32>,
+        _duration: Duration,
+    ) {
+    }
+
+    fn block_finished(&mut self, _block: &CodeBlock, _report: &BlockReport) {}
+
+    fn run_finished(&mut self, _reports: &[BlockReport], _duration: Duration) {}
+
+    /// A runme error stopped the run; `run_finished` will not follow.
+    fn run_aborted(&mut self, _error: &anyhow::Error) {}
+}
+
+/// No-op observer for callers that only want the final reports.
+impl ExecEvents for () {}
+
+/// Forwards every event to each observer in turn, e.g. live terminal output plus a
+/// JSON Lines file.
+impl ExecEvents for Vec<Box<dyn ExecEvents>> {
+    fn run_started(&mut self, files: &[PathBuf], blocks: usize) {
+        for events in self {
+            events.run_started(files, blocks);
+        }
+    }
+
+    fn block_started(&mut self, block: &CodeBlock) {
+        for events in self {
+            events.block_started(block);
+        }
+    }
+
+    fn line_started(&mut self, block: &CodeBlock, line: Option<usize>, command: &str) {
+        for events in self {
+            events.line_started(block, line, command);
+        }
+    }
+
+    fn stdout(&mut self, block: &CodeBlock, line: Option<usize>, chunk: &str) {
+        for events in self {
+            events.stdout(block, line, chunk);
+        }
+    }
+
+    fn stderr(&mut self, block: &CodeBlock, line: Option<usize>, chunk: &str) {
+        for events in self {
+            events.stderr(block, line, chunk);
+        }
+    }
+
+    fn line_finished(
+        &mut self,
+        block: &CodeBlock,
+        line: Option<usize>,
+        exit_code: Option<i32>,
+        duration: Duration,
+    ) {
+        for events in self {
+            events.line_finished(block, line, exit_code, duration);
+        }
+    }
+
+    fn block_finished(&mut self, block: &CodeBlock, report: &BlockReport) {
+        for events in self {
+            events.block_finished(block, report);
+        }
+    }
+
+    fn run_finished(&mut self, reports: &[BlockReport], duration: Duration) {
+        for events in self {
+            events.run_finished(reports, duration);
+        }
+    }
+
+    fn run_aborted(&mut self, error: &anyhow::Error) {
+        for events in self {
+            events.run_aborted(error);
+        }
+    }
+}
+
+/// Echo stdout/stderr to the terminal while commands run, labelled with their block.
+#[derive(Default)]
+pub struct HumanStreamer {
+    stdout_header: bool,
+    stderr_header: bool,
+    command: String,
+}
+
+impl HumanStreamer {
+    fn label(block: &CodeBlock) -> String {
+        let label = match &block.name {
+            Some(name) => format!("{} ({})", block.id, name),
+            None => block.id.clone(),
+        };
+        match &block.file {
+            Some(file) => format!("{}#{label}", file.display()),
+            None => label,
+        }
+    }
+}
+
+impl ExecEvents for HumanStreamer {
+    fn line_started(&mut self, _block: &CodeBlock, _line: Option<usize>, command: &str) {
+        self.stdout_header = false;
+        self.stderr_header = false;
+        self.command = command.to_string();
+    }
+
+    fn stdout(&mut self, block: &CodeBlock, _line: Option<usize>, chunk: &str) {
+        if !std::mem::replace(&mut self.stdout_header, true) {
+            println!("\x1b[36m[{}]\x1b[0m $ {}", Self::label(block), self.command);
+        }
+        println!("{chunk}");
+    }
+
+    fn stderr(&mut self, block: &CodeBlock, _line: Option<usize>, chunk: &str) {
+        if !std::mem::replace(&mut self.stderr_header, true) {
+            eprintln!(
+                "\x1b[31m[{}]\x1b[0m $ {} (stderr)",
+                Self::label(block),
+                self.command
+            );
+        }
+        eprintln!("\x1b[31m{chunk}\x1b[0m");
+    }
+}
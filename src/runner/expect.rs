@@ -0,0 +1,378 @@
+//! Compare captured stdout against `runme:expect` blocks.
+//!
+//! Both sides are compared as their non-blank lines with trailing whitespace
+//! removed, after the document's normalization rules. Updates are written from
+//! the raw lines instead, so blank lines and spacing in the output survive.
+//! Expected lines may use matchers for output that changes between runs:
+//!
+//! - `...` on its own line matches any number of lines (including none);
+//! - `[..]` inside a line matches any run of characters on that line;
+//! - `re:/regex/` on its own line matches the whole line against the regex, so output
+//!   such as `/usr/local/` can still be expected literally.
+
+use std::borrow::Cow;
+use std::path::Path;
+use std::sync::LazyLock;
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use similar::TextDiff;
+
+use crate::markdown::Normalization;
+
+const ELLIPSIS: &str = "...";
+const INLINE_WILDCARD: &str = "[..]";
+const REGEX_PREFIX: &str = "re:/";
+
+static ANSI_ESCAPE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\x1b\[[0-9;?]*[ -/]*[@-~]").expect("valid ANSI regex"));
+static WHITESPACE_RUN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\s+").expect("valid whitespace regex"));
+
+/// Details about output that did not match its `runme:expect` block.
+pub(super) struct Mismatch {
+    /// Unified diff from the expected text to the normalized actual output, or why the
+    /// expected block could not be used.
+    pub diff: String,
+    /// Expected block rewritten to fit the actual output: lines and matchers that still match
+    /// are kept as written, the rest is replaced by the captured lines (with the workdir
+    /// rewritten when that rule is on), blank lines included. `None` when the block has an
+    /// invalid matcher, which needs fixing by hand.
+    pub updated: Option<String>,
+}
+
+/// Describe how `actual` differs from `expected`, or return `None` when it matches.
+///
+/// `workdir` is the sandbox working directory used by [`Normalization::Workdir`].
+pub(super) fn compare(
+    expected: &str,
+    actual: &str,
+    rules: &[Normalization],
+    workdir: Option<&Path>,
+) -> Option<Mismatch> {
+    let expected_raw: Vec<&str> = expected.lines().collect();
+    let actual_raw: Vec<&str> = actual.lines().collect();
+    let expected = normalize(&expected_raw, rules, None);
+    let actual = normalize(&actual_raw, rules, workdir);
+
+    let patterns = match expected
+        .iter()
+        .map(|(_, line)| LinePattern::parse(line))
+        .collect::<Result<Vec<_>>>()
+    {
+        Ok(patterns) => patterns,
+        Err(err) => {
+            return Some(Mismatch {
+                diff: format!("{err:#}"),
+                updated: None,
+            });
+        }
+    };
+    let actual_lines: Vec<&str> = actual.iter().map(|(_, line)| line.as_str()).collect();
+    if matches_lines(&patterns, &actual_lines) {
+        return None;
+    }
+
+    let expected_text = join_lines(&expected);
+    let actual_text = join_lines(&actual);
+    let diff = TextDiff::from_lines(&expected_text, &actual_text)
+        .unified_diff()
+        .context_radius(3)
+        .header("expected", "actual")
+        .to_string();
+    let fresh_line = |line: &str| match workdir {
+        Some(dir) if rules.contains(&Normalization::Workdir) => {
+            line.replace(dir.to_string_lossy().as_ref(), "$WORKDIR")
+        }
+        _ => line.to_string(),
+    };
+    let updated =
+        merge(&patterns, &actual_lines)
+            .into_iter()
+            .fold(String::new(), |mut out, step| {
+                let (before, line) = match step {
+                    Step::Keep(i, matched) => {
+                        // Blank lines come from the output where the line was matched against it.
+                        let before = match matched {
+                            Some(j) => blanks_before(&actual, j),
+                            None => blanks_before(&expected, i),
+                        };
+                        (before, expected_raw[expected[i].0].to_string())
+                    }
+                    Step::Fresh(j) => (
+                        blanks_before(&actual, j),
+                        fresh_line(actual_raw[actual[j].0]),
+                    ),
+                };
+                out.push_str(&"\n".repeat(before));
+                out.push_str(&line);
+                out.push('\n');
+                out
+            });
+    Some(Mismatch {
+        diff,
+        updated: Some(updated),
+    })
+}
+
+/// One line of a rewritten expected block, indexing the normalized lines.
+enum Step {
+    /// Keep expected line `i`, matched against actual line `j` unless it is `...`.
+    Keep(usize, Option<usize>),
+    /// Write actual line `j`.
+    Fresh(usize),
+}
+
+/// Blank lines between normalized line `k` and the one before it in their source text.
+fn blanks_before(lines: &[(usize, String)], k: usize) -> usize {
+    let previous = k.checked_sub(1).map_or(0, |previous| lines[previous].0 + 1);
+    lines[k].0 - previous
+}
+
+/// Rewrite `expected` so it matches `actual` with as few changed lines as possible.
+///
+/// Aligns patterns with actual lines like an edit distance where a matching pattern (or `...`
+/// absorbing lines) is free, and replacing, dropping or adding a line costs one. Ties prefer
+/// replacing a line in place.
+fn merge(patterns: &[LinePattern], actual: &[&str]) -> Vec<Step> {
+    let (n, m) = (patterns.len(), actual.len());
+    // cost[i][j]: edits needed for the first `i` patterns to match the first `j` actual lines.
+    let mut cost = vec![vec![0usize; m + 1]; n + 1];
+    cost[0] = (0..=m).collect();
+    for i in 1..=n {
+        for j in 0..=m {
+            cost[i][j] = match &patterns[i - 1] {
+                LinePattern::AnyLines if j > 0 => cost[i - 1][j].min(cost[i][j - 1]),
+                LinePattern::AnyLines => cost[i - 1][j],
+                pattern => {
+                    let mut best = cost[i - 1][j] + 1;
+                    if j > 0 {
+                        best = best.min(cost[i][j - 1] + 1);
+                        let replace = usize::from(!pattern.matches(actual[j - 1]));
+                        best = best.min(cost[i - 1][j - 1] + replace);
+                    }
+                    best
+                }
+            };
+        }
+    }
+
+    let mut steps = Vec::new();
+    let (mut i, mut j) = (n, m);
+    while i > 0 || j > 0 {
+        if i == 0 {
+            steps.push(Step::Fresh(j - 1));
+            j -= 1;
+            continue;
+        }
+        match &patterns[i - 1] {
+            LinePattern::AnyLines => {
+                if j > 0 && cost[i][j] == cost[i][j - 1] {
+                    j -= 1;
+                } else {
+                    steps.push(Step::Keep(i - 1, None));
+                    i -= 1;
+                }
+            }
+            pattern => {
+                let matched = j > 0 && pattern.matches(actual[j - 1]);
+                if matched && cost[i][j] == cost[i - 1][j - 1] {
+                    steps.push(Step::Keep(i - 1, Some(j - 1)));
+                    i -= 1;
+                    j -= 1;
+                } else if j > 0 && !matched && cost[i][j] == cost[i - 1][j - 1] + 1 {
+                    steps.push(Step::Fresh(j - 1));
+                    i -= 1;
+                    j -= 1;
+                } else if j > 0 && cost[i][j] == cost[i][j - 1] + 1 {
+                    steps.push(Step::Fresh(j - 1));
+                    j -= 1;
+                } else {
+                    i -= 1;
+                }
+            }
+        }
+    }
+    steps.reverse();
+    steps
+}
+
+/// Remove ANSI color and cursor escape sequences.
+pub fn strip_ansi(text: &str) -> Cow<'_, str> {
+    ANSI_ESCAPE.replace_all(text, "")
+}
+
+/// Lines that take part in matching, each with its index in `lines`; blank lines are left out.
+fn normalize(
+    lines: &[&str],
+    rules: &[Normalization],
+    workdir: Option<&Path>,
+) -> Vec<(usize, String)> {
+    let mut normalized = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        let mut line = line.to_string();
+        for rule in rules {
+            match rule {
+                Normalization::StripAnsi => {
+                    line = strip_ansi(&line).into_owned();
+                }
+                Normalization::Workdir => {
+                    if let Some(dir) = workdir.map(|dir| dir.to_string_lossy()) {
+                        line = line.replace(dir.as_ref(), "$WORKDIR");
+                    }
+                }
+                Normalization::CollapseWhitespace => {
+                    line = WHITESPACE_RUN.replace_all(line.trim(), " ").into_owned();
+                }
+            }
+        }
+        let line = line.trim_end();
+        if !line.is_empty() {
+            normalized.push((index, line.to_string()));
+        }
+    }
+    normalized
+}
+
+fn join_lines(lines: &[(usize, String)]) -> String {
+    lines.iter().map(|(_, line)| format!("{line}\n")).collect()
+}
+
+enum LinePattern<'a> {
+    Literal(&'a str),
+    AnyLines,
+    Regex(Regex),
+}
+
+impl<'a> LinePattern<'a> {
+    fn parse(line: &'a str) -> Result<Self> {
+        let trimmed = line.trim();
+        if trimmed == ELLIPSIS {
+            return Ok(Self::AnyLines);
+        }
+        if let Some(source) = trimmed
+            .strip_prefix(REGEX_PREFIX)
+            .and_then(|rest| rest.strip_suffix('/'))
+        {
+            let regex = Regex::new(&format!("^(?:{source})$"))
+                .with_context(|| format!("invalid regex line {trimmed} in runme:expect block"))?;
+            return Ok(Self::Regex(regex));
+        }
+        if line.contains(INLINE_WILDCARD) {
+            let pieces: Vec<String> = line.split(INLINE_WILDCARD).map(regex::escape).collect();
+            let regex = Regex::new(&format!("^{}$", pieces.join(".*")))
+                .expect("escaped wildcard pattern is a valid regex");
+            return Ok(Self::Regex(regex));
+        }
+        Ok(Self::Literal(line))
+    }
+
+    fn matches(&self, line: &str) -> bool {
+        match self {
+            Self::Literal(expected) => *expected == line,
+            Self::AnyLines => true,
+            Self::Regex(regex) => regex.is_match(line),
+        }
+    }
+}
+
+/// Classic wildcard matching over lines; `reachable[j]` means the patterns consumed so far can
+/// match exactly the first `j` actual lines.
+fn matches_lines(patterns: &[LinePattern], actual: &[&str]) -> bool {
+    let mut reachable = vec![false; actual.len() + 1];
+    reachable[0] = true;
+
+    for pattern in patterns {
+        let mut next = vec![false; actual.len() + 1];
+        for j in 0..=actual.len() {
+            match pattern {
+                LinePattern::AnyLines => next[j] = reachable[j] || (j > 0 && next[j - 1]),
+                _ => next[j] = j > 0 && reachable[j - 1] && pattern.matches(actual[j - 1]),
+            }
+        }
+        reachable = next;
+    }
+
+    reachable[actual.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(expected: &str, actual: &str, rules: &[Normalization]) -> bool {
+        compare(expected, actual, rules, Some(Path::new("/tmp/docs"))).is_none()
+    }
+
+    #[test]
+    fn ellipsis_and_wildcards_absorb_volatile_output() {
+        let actual = "build started at 12:01\nstep 1\nstep 2\nstep 3\ndone in 4.2s\n";
+        assert!(check(
+            "build started at [..]\n...\ndone in [..]s",
+            actual,
+            &[]
+        ));
+        assert!(check("...\nstep 2\n...", actual, &[]));
+        assert!(!check("build started at [..]\ndone in [..]s", actual, &[]));
+        assert!(check(
+            "re:/build started at \\d{2}:\\d{2}/\n...\nre:/done in [0-9.]+s/",
+            actual,
+            &[]
+        ));
+    }
+
+    #[test]
+    fn slashes_are_literal_without_the_regex_marker() {
+        assert!(check("/usr/local/\n/(a|b)/", "/usr/local/\n/(a|b)/\n", &[]));
+        assert!(!check("/usr/local/", "/usr/lib/\n", &[]));
+
+        let mismatch = compare("re:/(unclosed/", "(unclosed\n", &[], None)
+            .expect("invalid regexes fail the block");
+        assert!(mismatch.diff.contains("invalid regex"), "{}", mismatch.diff);
+        assert!(mismatch.updated.is_none());
+    }
+
+    #[test]
+    fn updates_keep_matchers_that_still_match() {
+        let actual = "id=42\nstep 1\nstep 2\nstatus: done\nextra\n";
+        let mismatch = compare("id=[..]\n...\nstatus: pending\nextra", actual, &[], None)
+            .expect("status drifted");
+        let updated = mismatch.updated.expect("matchers compile");
+        assert_eq!(updated, "id=[..]\n...\nstatus: done\nextra\n");
+        assert!(check(&updated, actual, &[]));
+    }
+
+    #[test]
+    fn updates_keep_blank_lines_and_raw_output() {
+        let mismatch = compare("a\n\nc", "a\n\nb\n", &[], None).expect("the last line drifted");
+        assert_eq!(mismatch.updated.as_deref(), Some("a\n\nb\n"));
+
+        let rules = [Normalization::CollapseWhitespace, Normalization::Workdir];
+        let actual = "id  7\n\n\nsaved   to /tmp/docs/out\n";
+        let mismatch = compare(
+            "id [..]\nsaved to elsewhere",
+            actual,
+            &rules,
+            Some(Path::new("/tmp/docs")),
+        )
+        .expect("the path drifted");
+        let updated = mismatch.updated.expect("matchers compile");
+        assert_eq!(updated, "id [..]\n\n\nsaved   to $WORKDIR/out\n");
+        assert!(check(&updated, actual, &rules));
+    }
+
+    #[test]
+    fn normalization_rules_rewrite_output_before_matching() {
+        let actual = "\x1b[32mok\x1b[0m   wrote  /tmp/docs/out.txt\n";
+        assert!(!check("ok wrote $WORKDIR/out.txt", actual, &[]));
+        assert!(check(
+            "ok wrote $WORKDIR/out.txt",
+            actual,
+            &[
+                Normalization::StripAnsi,
+                Normalization::CollapseWhitespace,
+                Normalization::Workdir,
+            ]
+        ));
+    }
+}
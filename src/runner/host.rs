@@ -1,10 +1,11 @@
 use std::path::PathBuf;
 use std::process::Command;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result, anyhow};
 
-use super::sandbox::{CommandStatus, OutputSink, Sandbox, spawn_with_streaming};
+use super::sandbox::{CommandStatus, OutputSink, Sandbox, Session, spawn_with_streaming};
+use super::session::ShellSession;
 
 /// Straightforward sandbox that shells out on the host OS.
 ///
@@ -27,7 +28,12 @@ impl Sandbox for HostSandbox {
         "host"
     }
 
-    fn run(&mut self, argv: &[String], sink: &mut dyn OutputSink) -> Result<CommandStatus> {
+    fn run(
+        &mut self,
+        argv: &[String],
+        timeout: Option<Duration>,
+        sink: &mut dyn OutputSink,
+    ) -> Result<CommandStatus> {
         let (binary, rest) = argv
             .split_first()
             .ok_or_else(|| anyhow!("sandbox run requires at least one argument"))?;
@@ -36,8 +42,19 @@ impl Sandbox for HostSandbox {
         cmd.args(rest).current_dir(&self.workdir);
 
         let start = Instant::now();
-        let output = spawn_with_streaming(cmd, sink)
+        let output = spawn_with_streaming(cmd, timeout, sink)
             .with_context(|| format!("while invoking {binary} inside host sandbox"))?;
         Ok(output.with_duration(start.elapsed()))
     }
+
+    fn workdir(&self) -> Option<PathBuf> {
+        self.workdir.canonicalize().ok()
+    }
+
+    fn open_session(&mut self) -> Result<Box<dyn Session>> {
+        let mut cmd = Command::new("bash");
+        cmd.args(["--noprofile", "--norc"])
+            .current_dir(&self.workdir);
+        Ok(Box::new(ShellSession::spawn(cmd)?))
+    }
 }
@@ -1,40 +1,93 @@
 mod docker;
+mod events;
+mod expect;
 mod host;
+#[cfg(target_os = "linux")]
+mod ns;
 pub mod sandbox;
+mod script;
+mod session;
 mod wasm;
 
-pub use docker::DockerSandbox;
+pub use docker::{ContainerRuntime, DockerSandbox, build_image};
+pub use events::{ExecEvents, HumanStreamer};
+pub use expect::strip_ansi;
 pub use host::HostSandbox;
+#[cfg(target_os = "linux")]
+pub use ns::NsSandbox;
 pub use sandbox::Sandbox;
-pub use wasm::WasmSandbox;
+pub use session::SessionPool;
+pub use wasm::{WasmLimits, WasmSandbox};
 
-use std::time::Duration;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result, anyhow};
 use serde::Serialize;
-use shlex;
 
-use crate::markdown::CodeBlock;
-use sandbox::OutputSink;
+use crate::markdown::{CodeBlock, DEFAULT_SESSION, ExecMode, Normalization, Phase, SourceLocation};
+use sandbox::{CommandStatus, OutputSink};
+
+/// Knobs shared by every block executed during a run.
+#[derive(Clone, Debug, Default)]
+pub struct ExecOptions {
+    /// Mode used for blocks without a `runme:mode` directive.
+    pub default_mode: ExecMode,
+    /// Run blocks without `runme:session` in the document's default session.
+    pub shared_session: bool,
+    /// Budget for blocks without a `runme:timeout` directive; `None` never times out.
+    pub default_timeout: Option<Duration>,
+    /// The document's `runme:normalize` rules, applied before comparing expected output.
+    pub normalize: Vec<Normalization>,
+}
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
 #[serde(tag = "status", rename_all = "snake_case")]
 pub enum BlockStatus {
     Passed,
-    Failed { exit_code: Option<i32> },
+    Failed {
+        exit_code: Option<i32>,
+    },
+    /// The block exceeded its timeout and its process tree was killed.
+    TimedOut {
+        timeout_ms: u128,
+    },
     Skipped,
 }
 
+impl BlockStatus {
+    /// Whether the block counts against the run (failed or timed out).
+    pub fn is_failure(&self) -> bool {
+        matches!(self, Self::Failed { .. } | Self::TimedOut { .. })
+    }
+}
+
 #[derive(Clone, Debug, Serialize)]
 pub struct BlockReport {
+    pub file: Option<PathBuf>,
     pub id: String,
+    pub location: SourceLocation,
     pub name: Option<String>,
     pub headings: Vec<String>,
     pub language: Option<String>,
     pub sandbox: Option<String>,
+    /// Persistent shell the block ran in, if any.
+    pub session: Option<String>,
+    /// Whether this was a setup, regular, or teardown block.
+    pub phase: Phase,
     pub duration_ms: u128,
     pub status: BlockStatus,
     pub skip_reason: Option<String>,
+    /// 1-based line within the block whose command failed, when known.
+    pub failed_line: Option<usize>,
+    /// Line in the markdown file matching `failed_line`.
+    pub failed_source_line: Option<usize>,
+    /// Unified diff between the `runme:expect` block and the captured stdout on mismatch, or
+    /// why the block could not be matched (e.g. an invalid regex line).
+    pub output_diff: Option<String>,
+    /// Normalized stdout that would make the `runme:expect` block match; feeds `runme update`.
+    #[serde(skip)]
+    pub updated_expectation: Option<String>,
     pub stdout: Option<String>,
     pub stderr: Option<String>,
 }
@@ -42,14 +95,22 @@ pub struct BlockReport {
 impl BlockReport {
     fn from_skip(block: &CodeBlock, reason: String) -> Self {
         Self {
+            file: block.file.clone(),
             id: block.id.clone(),
+            location: block.location,
             name: block.name.clone(),
             headings: block.headings.clone(),
             language: block.language.clone(),
             sandbox: None,
+            session: None,
+            phase: block.phase,
             duration_ms: 0,
             status: BlockStatus::Skipped,
             skip_reason: Some(reason),
+            failed_line: None,
+            failed_source_line: None,
+            output_diff: None,
+            updated_expectation: None,
             stdout: None,
             stderr: None,
         }
@@ -57,10 +118,35 @@ impl BlockReport {
 }
 
 /// Execute a parsed code block using a shell interpreter when possible.
+///
+/// `events` observes the block, each of its commands, and their output as they happen.
 pub fn execute(
     block: &CodeBlock,
     sandbox: &mut dyn Sandbox,
-    stream_live: bool,
+    sessions: &mut SessionPool,
+    options: &ExecOptions,
+    events: &mut dyn ExecEvents,
+) -> Result<BlockReport> {
+    events.block_started(block);
+    let report = execute_block(block, sandbox, sessions, options, events)?;
+    events.block_finished(block, &report);
+    Ok(report)
+}
+
+/// Report `block` as skipped without running it, e.g. because a prerequisite failed.
+pub fn skip(block: &CodeBlock, reason: String, events: &mut dyn ExecEvents) -> BlockReport {
+    events.block_started(block);
+    let report = BlockReport::from_skip(block, reason);
+    events.block_finished(block, &report);
+    report
+}
+
+fn execute_block(
+    block: &CodeBlock,
+    sandbox: &mut dyn Sandbox,
+    sessions: &mut SessionPool,
+    options: &ExecOptions,
+    events: &mut dyn ExecEvents,
 ) -> Result<BlockReport> {
     if let Some(reason) = block.skip_reason.clone() {
         return Ok(BlockReport::from_skip(block, reason));
@@ -84,72 +170,191 @@ pub fn execute(
     }
 
     let sandbox_label = sandbox.label().to_string();
-    let mut total_duration = Duration::default();
-    let mut stdout_chunks = Vec::new();
-    let mut stderr_chunks = Vec::new();
-    let mut status = BlockStatus::Passed;
-    let mut executed_lines = 0_usize;
-
-    for (idx, raw_line) in block.content.lines().enumerate() {
-        let trimmed = raw_line.trim();
-        if trimmed.is_empty() || trimmed.starts_with('#') {
-            continue;
-        }
-
-        let args =
-            shlex::split(trimmed).ok_or_else(|| anyhow!("unable to parse line {}", idx + 1))?;
-        if args.is_empty() {
-            continue;
-        }
-        executed_lines += 1;
-
-        let mut transcript = CommandTranscript::new(trimmed);
-        let mut sink = TranscriptSink::new(&mut transcript, stream_live.then_some(block));
-        let outcome = sandbox
-            .run(&args, &mut sink)
-            .with_context(|| format!("while executing {} line {}", block.id, idx + 1))?;
-
-        if let Some(line_stdout) = transcript.stdout {
-            stdout_chunks.push(line_stdout);
+    let mode = block.mode.unwrap_or(options.default_mode);
+    let session_name = block
+        .session
+        .as_deref()
+        .or(options.shared_session.then_some(DEFAULT_SESSION));
+    let timeout = block.timeout.or(options.default_timeout);
+    let mut execution = match (session_name, mode) {
+        (Some(name), ExecMode::Lines) => {
+            let session = sessions.get_or_open(name, sandbox)?;
+            let commands = session::group_commands(&block.content);
+            execute_lines(
+                block,
+                &commands,
+                timeout,
+                events,
+                |line, remaining, sink| session.run(line, remaining, sink),
+            )?
         }
-        if let Some(line_stderr) = transcript.stderr {
-            stderr_chunks.push(line_stderr);
+        (Some(name), ExecMode::Script) => {
+            let session = sessions.get_or_open(name, sandbox)?;
+            script::execute_in_session(block, session, name, timeout, events)?
         }
-        total_duration += outcome.duration;
-
-        if !outcome.success {
-            status = BlockStatus::Failed {
-                exit_code: outcome.exit_code,
-            };
-            break;
-        }
-    }
-
-    if executed_lines == 0 {
+        (None, ExecMode::Lines) => execute_lines(
+            block,
+            &plain_lines(&block.content),
+            timeout,
+            events,
+            |line, remaining, sink| {
+                let args = shlex::split(line).ok_or_else(|| anyhow!("unable to parse `{line}`"))?;
+                sandbox.run(&args, remaining, sink)
+            },
+        )?,
+        (None, ExecMode::Script) => script::execute_script(block, sandbox, timeout, events)?,
+    };
+
+    if execution.executed_lines == 0 {
         return Ok(BlockReport::from_skip(
             block,
             "Block only had comments/blank lines".into(),
         ));
     }
 
+    let mut mismatch = None;
+    if let (None, Some(expected)) = (&execution.status, &block.expected_stdout) {
+        let workdir = sandbox.workdir();
+        mismatch = expect::compare(
+            expected,
+            &execution.captured_stdout,
+            &options.normalize,
+            workdir.as_deref(),
+        );
+        if mismatch.is_some() {
+            // Every command exited cleanly; only the output disagrees with the docs.
+            execution.status = Some(BlockStatus::Failed { exit_code: Some(0) });
+        }
+    }
+    let (output_diff, updated_expectation) = match mismatch {
+        Some(mismatch) => (Some(mismatch.diff), mismatch.updated),
+        None => (None, None),
+    };
+
     Ok(BlockReport {
+        file: block.file.clone(),
         id: block.id.clone(),
+        location: block.location,
         name: block.name.clone(),
         headings: block.headings.clone(),
         language: block.language.clone(),
         sandbox: Some(sandbox_label),
-        duration_ms: total_duration.as_millis(),
-        status,
+        session: session_name.map(str::to_string),
+        phase: block.phase,
+        duration_ms: execution.duration.as_millis(),
+        status: execution.status.unwrap_or(BlockStatus::Passed),
         skip_reason: None,
-        stdout: (!stdout_chunks.is_empty()).then(|| stdout_chunks.join("\n")),
-        stderr: (!stderr_chunks.is_empty()).then(|| stderr_chunks.join("\n")),
+        failed_line: execution.failed_line,
+        failed_source_line: execution
+            .failed_line
+            .map(|line| block.location.source_line(line)),
+        output_diff,
+        updated_expectation,
+        stdout: join_chunks(execution.stdout_chunks),
+        stderr: join_chunks(execution.stderr_chunks),
     })
 }
 
+fn join_chunks(chunks: Vec<String>) -> Option<String> {
+    (!chunks.is_empty()).then(|| chunks.join("\n"))
+}
+
+/// Raw results gathered while a block runs, before they are folded into a `BlockReport`.
+#[derive(Default)]
+struct Execution {
+    stdout_chunks: Vec<String>,
+    stderr_chunks: Vec<String>,
+    /// Plain stdout of every command, without transcript headers, for `runme:expect`.
+    captured_stdout: String,
+    status: Option<BlockStatus>,
+    duration: Duration,
+    executed_lines: usize,
+    failed_line: Option<usize>,
+}
+
+impl Execution {
+    /// Fold a non-successful command outcome into the block status.
+    fn fail(&mut self, outcome: &CommandStatus, timeout: Option<Duration>) {
+        self.status = Some(match timeout {
+            Some(timeout) if outcome.timed_out => BlockStatus::TimedOut {
+                timeout_ms: timeout.as_millis(),
+            },
+            _ => BlockStatus::Failed {
+                exit_code: outcome.exit_code,
+            },
+        });
+    }
+
+    fn record(&mut self, transcript: CommandTranscript) {
+        self.captured_stdout.push_str(&transcript.captured_stdout);
+        if let Some(stdout) = transcript.stdout {
+            self.stdout_chunks.push(stdout);
+        }
+        if let Some(stderr) = transcript.stderr {
+            self.stderr_chunks.push(stderr);
+        }
+    }
+}
+
+/// Non-blank, non-comment lines with their 0-based index, each run as its own command.
+fn plain_lines(content: &str) -> Vec<(usize, String)> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| (idx, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(idx, line)| (idx, line.to_string()))
+        .collect()
+}
+
+/// Run each command through `run`, stopping at the first failure.
+///
+/// `timeout` covers the whole block; each command receives whatever budget is left.
+fn execute_lines(
+    block: &CodeBlock,
+    commands: &[(usize, String)],
+    timeout: Option<Duration>,
+    events: &mut dyn ExecEvents,
+    mut run: impl FnMut(&str, Option<Duration>, &mut dyn OutputSink) -> Result<CommandStatus>,
+) -> Result<Execution> {
+    let mut execution = Execution::default();
+    let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+
+    for &(idx, ref command) in commands {
+        execution.executed_lines += 1;
+
+        let line = Some(idx + 1);
+        events.line_started(block, line, command);
+        let mut transcript = CommandTranscript::new(command);
+        let mut sink = TranscriptSink {
+            transcript: &mut transcript,
+            block,
+            line,
+            events: &mut *events,
+        };
+        let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        let outcome = run(command, remaining, &mut sink)
+            .with_context(|| format!("while executing {} line {}", block.id, idx + 1))?;
+        events.line_finished(block, line, outcome.exit_code, outcome.duration);
+
+        execution.record(transcript);
+        execution.duration += outcome.duration;
+
+        if !outcome.success {
+            execution.fail(&outcome, timeout);
+            execution.failed_line = Some(idx + 1);
+            break;
+        }
+    }
+
+    Ok(execution)
+}
+
 struct CommandTranscript<'a> {
     command: &'a str,
     stdout: Option<String>,
     stderr: Option<String>,
+    captured_stdout: String,
 }
 
 impl<'a> CommandTranscript<'a> {
@@ -158,109 +363,92 @@ impl<'a> CommandTranscript<'a> {
             command,
             stdout: None,
             stderr: None,
+            captured_stdout: String::new(),
         }
     }
 
-    fn append_stdout(&mut self, chunk: &str) -> bool {
+    /// Blank lines only reach `captured_stdout`; transcripts and observers skip them.
+    fn append_stdout(&mut self, chunk: &str) {
+        self.captured_stdout.push_str(chunk);
+        self.captured_stdout.push('\n');
         if chunk.is_empty() {
-            return false;
+            return;
         }
-        let first = self.stdout.is_none();
         let entry = self
             .stdout
             .get_or_insert_with(|| format!("$ {}\n", self.command));
         entry.push_str(chunk);
         entry.push('\n');
-        first
     }
 
-    fn append_stderr(&mut self, chunk: &str) -> bool {
+    fn append_stderr(&mut self, chunk: &str) {
         if chunk.is_empty() {
-            return false;
+            return;
         }
-        let first = self.stderr.is_none();
         let entry = self
             .stderr
             .get_or_insert_with(|| format!("$ {}\n", self.command));
         entry.push_str(chunk);
         entry.push('\n');
-        first
     }
 }
 
+/// Feeds one command's output into its transcript and the run's observer.
 struct TranscriptSink<'a, 'b> {
     transcript: &'a mut CommandTranscript<'b>,
-    streamer: Option<HumanStreamer>,
-}
-
-impl<'a, 'b> TranscriptSink<'a, 'b> {
-    fn new(transcript: &'a mut CommandTranscript<'b>, block: Option<&'b CodeBlock>) -> Self {
-        Self {
-            transcript,
-            streamer: block.map(HumanStreamer::new),
-        }
-    }
+    block: &'b CodeBlock,
+    line: Option<usize>,
+    events: &'a mut dyn ExecEvents,
 }
 
 impl OutputSink for TranscriptSink<'_, '_> {
     fn on_stdout(&mut self, chunk: &str) {
-        let first = self.transcript.append_stdout(chunk);
-        if let Some(streamer) = self.streamer.as_mut() {
-            streamer.on_stdout(self.transcript.command, chunk, first);
+        self.transcript.append_stdout(chunk);
+        if !chunk.is_empty() {
+            self.events.stdout(self.block, self.line, chunk);
         }
     }
 
     fn on_stderr(&mut self, chunk: &str) {
-        let first = self.transcript.append_stderr(chunk);
-        if let Some(streamer) = self.streamer.as_mut() {
-            streamer.on_stderr(self.transcript.command, chunk, first);
-        }
-    }
-}
-
-struct HumanStreamer {
-    label: String,
-}
-
-impl HumanStreamer {
-    fn new(block: &CodeBlock) -> Self {
-        let label = if let Some(name) = &block.name {
-            format!("{} ({})", block.id, name)
-        } else {
-            block.id.clone()
-        };
-        Self { label }
-    }
-
-    fn on_stdout(&mut self, command: &str, chunk: &str, first: bool) {
-        if first {
-            println!("\x1b[36m[{}]\x1b[0m $ {}", self.label, command);
-        }
-        println!("{chunk}");
-    }
-
-    fn on_stderr(&mut self, command: &str, chunk: &str, first: bool) {
-        if first {
-            eprintln!("\x1b[31m[{}]\x1b[0m $ {} (stderr)", self.label, command);
+        self.transcript.append_stderr(chunk);
+        if !chunk.is_empty() {
+            self.events.stderr(self.block, self.line, chunk);
         }
-        eprintln!("\x1b[31m{chunk}\x1b[0m");
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::markdown::extract_blocks;
     use std::env;
 
     /// Helper to craft a shell-ready block while keeping headings/context realistic.
     fn shell_block(script: &str) -> CodeBlock {
         CodeBlock {
             id: "block-test".into(),
+            file: None,
             name: None,
             headings: vec!["Tests".into()],
             language: Some("bash".into()),
             content: script.trim().to_string(),
             skip_reason: None,
+            mode: None,
+            session: None,
+            timeout: None,
+            tags: Vec::new(),
+            needs: Vec::new(),
+            phase: Phase::Main,
+            expected_stdout: None,
+            range: 0..0,
+            location: SourceLocation {
+                line: 1,
+                column: 1,
+                end_line: script.trim().lines().count() + 2,
+                end_column: 4,
+                content_line: 2,
+            },
+            expected_span: None,
         }
     }
 
@@ -275,8 +463,14 @@ mod tests {
         block.skip_reason = Some("user opted out".into());
 
         let mut sandbox = host_sandbox();
-        let report =
-            execute(&block, &mut sandbox, false).expect("skip handling should succeed without IO");
+        let report = execute(
+            &block,
+            &mut sandbox,
+            &mut SessionPool::default(),
+            &ExecOptions::default(),
+            &mut (),
+        )
+        .expect("skip handling should succeed without IO");
 
         assert!(matches!(report.status, BlockStatus::Skipped));
         assert_eq!(report.skip_reason.as_deref(), Some("user opted out"));
@@ -291,8 +485,14 @@ mod tests {
         };
 
         let mut sandbox = host_sandbox();
-        let report = execute(&block, &mut sandbox, false)
-            .expect("unsupported languages still yield clean reports");
+        let report = execute(
+            &block,
+            &mut sandbox,
+            &mut SessionPool::default(),
+            &ExecOptions::default(),
+            &mut (),
+        )
+        .expect("unsupported languages still yield clean reports");
 
         assert!(matches!(report.status, BlockStatus::Skipped));
         assert!(
@@ -310,8 +510,14 @@ mod tests {
         let block = shell_block("echo runner-ok");
 
         let mut sandbox = host_sandbox();
-        let report =
-            execute(&block, &mut sandbox, false).expect("echo should succeed on every platform");
+        let report = execute(
+            &block,
+            &mut sandbox,
+            &mut SessionPool::default(),
+            &ExecOptions::default(),
+            &mut (),
+        )
+        .expect("echo should succeed on every platform");
 
         assert!(matches!(report.status, BlockStatus::Passed));
         let stdout = report.stdout.expect("stdout is captured");
@@ -326,8 +532,14 @@ mod tests {
         let block = shell_block("false\necho never");
 
         let mut sandbox = host_sandbox();
-        let report =
-            execute(&block, &mut sandbox, false).expect("erroring commands still return a report");
+        let report = execute(
+            &block,
+            &mut sandbox,
+            &mut SessionPool::default(),
+            &ExecOptions::default(),
+            &mut (),
+        )
+        .expect("erroring commands still return a report");
 
         match report.status {
             BlockStatus::Failed { exit_code } => {
@@ -339,6 +551,12 @@ mod tests {
             report.stdout.is_none(),
             "execution stops before later lines"
         );
+        assert_eq!(report.failed_line, Some(1));
+        assert_eq!(
+            report.failed_source_line,
+            Some(2),
+            "content starts below the fence"
+        );
         assert_eq!(report.sandbox.as_deref(), Some("host"));
     }
 
@@ -348,8 +566,14 @@ mod tests {
         let block = shell_block("# this is documentation");
 
         let mut sandbox = host_sandbox();
-        let report =
-            execute(&block, &mut sandbox, false).expect("comment-only block is a valid skip case");
+        let report = execute(
+            &block,
+            &mut sandbox,
+            &mut SessionPool::default(),
+            &ExecOptions::default(),
+            &mut (),
+        )
+        .expect("comment-only block is a valid skip case");
 
         assert!(matches!(report.status, BlockStatus::Skipped));
         assert_eq!(
@@ -358,6 +582,270 @@ mod tests {
         );
     }
 
+    #[test]
+    fn script_mode_supports_shell_syntax() {
+        // Pipes, variables and conditionals only work when the interpreter sees the whole block.
+        let mut block =
+            shell_block("GREETING=script-ok\nif true; then\n  echo \"$GREETING\" | tr a-z A-Z\nfi");
+        block.mode = Some(ExecMode::Script);
+
+        let mut sandbox = host_sandbox();
+        let report = execute(
+            &block,
+            &mut sandbox,
+            &mut SessionPool::default(),
+            &ExecOptions::default(),
+            &mut (),
+        )
+        .expect("script blocks run through bash");
+
+        assert!(matches!(report.status, BlockStatus::Passed));
+        assert!(
+            report
+                .stdout
+                .expect("stdout captured")
+                .contains("SCRIPT-OK")
+        );
+    }
+
+    #[test]
+    fn script_mode_attributes_failures_to_source_lines() {
+        // xtrace markers map the failing command back to its line and never leak into stderr.
+        // Stdout races the markers, so the sleep lets line 3's marker arrive before its output.
+        let block =
+            shell_block("echo first\necho oops >&2\nsleep 0.2; echo second\nfalse\necho never");
+        let options = ExecOptions {
+            default_mode: ExecMode::Script,
+            ..ExecOptions::default()
+        };
+
+        let mut sandbox = host_sandbox();
+        let report = execute(
+            &block,
+            &mut sandbox,
+            &mut SessionPool::default(),
+            &options,
+            &mut (),
+        )
+        .expect("failing scripts still yield reports");
+
+        assert!(matches!(
+            report.status,
+            BlockStatus::Failed { exit_code: Some(1) }
+        ));
+        assert_eq!(report.failed_line, Some(4));
+        let stderr = report.stderr.expect("stderr captured");
+        assert_eq!(stderr, "$ echo oops >&2\noops\n");
+        let stdout = report.stdout.expect("stdout captured");
+        let (_, third) = stdout
+            .split_once("$ sleep 0.2; echo second\n")
+            .expect("line 3 has a stdout transcript");
+        assert!(third.contains("second"), "{stdout}");
+        assert!(!stdout.contains("never"));
+    }
+
+    #[test]
+    fn sessions_carry_state_across_lines_and_blocks() {
+        // `cd` and `export` in one block must still apply to later blocks naming the same session.
+        let mut setup = shell_block("cd src\nexport RUNME_SESSION_VAR=kept");
+        setup.session = Some("tutorial".into());
+        let mut follow_up = shell_block("echo \"$RUNME_SESSION_VAR\"\nls runner | grep session.rs");
+        follow_up.session = Some("tutorial".into());
+        let isolated = shell_block("printenv RUNME_SESSION_VAR");
+
+        let mut sandbox = host_sandbox();
+        let mut sessions = SessionPool::default();
+        let options = ExecOptions::default();
+        let first =
+            execute(&setup, &mut sandbox, &mut sessions, &options, &mut ()).expect("setup runs");
+        assert!(matches!(first.status, BlockStatus::Passed));
+        assert_eq!(first.session.as_deref(), Some("tutorial"));
+
+        let second = execute(&follow_up, &mut sandbox, &mut sessions, &options, &mut ())
+            .expect("follow-up runs");
+        assert!(matches!(second.status, BlockStatus::Passed));
+        let stdout = second.stdout.expect("session output captured");
+        assert!(stdout.contains("kept"));
+        assert!(stdout.contains("session.rs"));
+
+        let third = execute(&isolated, &mut sandbox, &mut sessions, &options, &mut ())
+            .expect("isolated runs");
+        assert!(
+            matches!(third.status, BlockStatus::Failed { .. }),
+            "blocks outside the session do not see its environment"
+        );
+    }
+
+    #[test]
+    fn session_lines_send_multi_line_commands_whole() {
+        // Heredocs, continuations and compound commands would swallow per-line sentinels.
+        let mut block = shell_block(
+            "cat <<EOF\n  # kept as written\nEOF\necho one \\\n  two\nif true; then\n  echo three\nfi\nfalse\necho never",
+        );
+        block.session = Some("heredoc".into());
+        block.timeout = Some(Duration::from_secs(10));
+
+        let mut sandbox = host_sandbox();
+        let report = execute(
+            &block,
+            &mut sandbox,
+            &mut SessionPool::default(),
+            &ExecOptions::default(),
+            &mut (),
+        )
+        .expect("session runs");
+        assert!(matches!(
+            report.status,
+            BlockStatus::Failed { exit_code: Some(1) }
+        ));
+        assert_eq!(report.failed_line, Some(9));
+        let stdout = report.stdout.expect("stdout captured");
+        assert!(stdout.contains("  # kept as written\n"), "{stdout}");
+        assert!(stdout.contains("one two\n"), "{stdout}");
+        assert!(stdout.contains("three\n"), "{stdout}");
+        assert!(!stdout.contains("never"), "{stdout}");
+    }
+
+    #[test]
+    fn session_scripts_stop_at_the_first_failure() {
+        // Errexit and pipefail apply to the block only; the session keeps its state and options,
+        // and `declare` assigns in the session's scope rather than a wrapper function's.
+        let mut script =
+            shell_block("declare RUNME_DECLARED=kept\nexport RUNME_KEPT=yes\nfalse\necho never");
+        script.mode = Some(ExecMode::Script);
+        script.session = Some("strict".into());
+        let mut piped = shell_block("false | true\necho \"$RUNME_KEPT $RUNME_DECLARED\"");
+        piped.session = Some("strict".into());
+
+        let mut sandbox = host_sandbox();
+        let mut sessions = SessionPool::default();
+        let options = ExecOptions::default();
+        let report =
+            execute(&script, &mut sandbox, &mut sessions, &options, &mut ()).expect("script runs");
+        assert!(matches!(
+            report.status,
+            BlockStatus::Failed { exit_code: Some(1) }
+        ));
+        assert!(!report.stdout.unwrap_or_default().contains("never"));
+
+        script.content = "false | true\necho never".into();
+        let report = execute(&script, &mut sandbox, &mut sessions, &options, &mut ())
+            .expect("pipeline runs");
+        assert!(matches!(report.status, BlockStatus::Failed { .. }));
+
+        let report =
+            execute(&piped, &mut sandbox, &mut sessions, &options, &mut ()).expect("lines run");
+        assert!(matches!(report.status, BlockStatus::Passed));
+        assert!(
+            report
+                .stdout
+                .expect("stdout captured")
+                .contains("yes kept\n")
+        );
+    }
+
+    #[test]
+    fn expected_output_mismatch_fails_with_diff() {
+        // Matching output passes; drift marks the block failed and explains it with a diff.
+        let mut block = shell_block("echo alpha\necho beta");
+        block.expected_stdout = Some("alpha\nbeta".into());
+
+        let mut sandbox = host_sandbox();
+        let options = ExecOptions::default();
+        let report = execute(
+            &block,
+            &mut sandbox,
+            &mut SessionPool::default(),
+            &options,
+            &mut (),
+        )
+        .expect("matching block runs");
+        assert!(matches!(report.status, BlockStatus::Passed));
+        assert!(report.output_diff.is_none());
+
+        block.expected_stdout = Some("alpha\ngamma".into());
+        let report = execute(
+            &block,
+            &mut sandbox,
+            &mut SessionPool::default(),
+            &options,
+            &mut (),
+        )
+        .expect("mismatching block still reports");
+        assert!(matches!(
+            report.status,
+            BlockStatus::Failed { exit_code: Some(0) }
+        ));
+        let diff = report.output_diff.expect("diff explains the mismatch");
+        assert!(diff.contains("-gamma"));
+        assert!(diff.contains("+beta"));
+        assert_eq!(report.updated_expectation.as_deref(), Some("alpha\nbeta\n"));
+    }
+
+    #[test]
+    fn timeouts_kill_the_process_tree_and_keep_partial_output() {
+        // The grandchild `sleep` holds the pipes open, so only a process-group kill returns fast.
+        let mut block = shell_block("echo before\nbash -c 'sleep 30 & wait'\necho never");
+        block.timeout = Some(Duration::from_millis(300));
+
+        let mut sandbox = host_sandbox();
+        let started = Instant::now();
+        for (mode, session) in [
+            (ExecMode::Lines, None),
+            (ExecMode::Script, None),
+            (ExecMode::Lines, Some("slow".to_string())),
+        ] {
+            block.mode = Some(mode);
+            block.session = session;
+            let report = execute(
+                &block,
+                &mut sandbox,
+                &mut SessionPool::default(),
+                &ExecOptions::default(),
+                &mut (),
+            )
+            .expect("timed-out blocks still report");
+
+            assert!(
+                matches!(report.status, BlockStatus::TimedOut { timeout_ms: 300 }),
+                "{mode:?}: {:?}",
+                report.status
+            );
+            assert!(report.status.is_failure());
+            assert_eq!(report.failed_line, Some(2), "{mode:?}");
+            let stdout = report.stdout.expect("partial output is kept");
+            assert!(stdout.contains("before") && !stdout.contains("never"));
+        }
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn huge_timeouts_behave_like_no_deadline() {
+        let blocks = extract_blocks(
+            "<!-- runme:timeout 10000000000000000000 -->\n```bash\necho fine\n```\n",
+        )
+        .expect("parse");
+        let mut block = blocks[0].clone();
+        let mut sandbox = host_sandbox();
+        for (mode, session) in [
+            (ExecMode::Lines, None),
+            (ExecMode::Script, None),
+            (ExecMode::Lines, Some("huge".to_string())),
+        ] {
+            block.mode = Some(mode);
+            block.session = session;
+            let report = execute(
+                &block,
+                &mut sandbox,
+                &mut SessionPool::default(),
+                &ExecOptions::default(),
+                &mut (),
+            )
+            .expect("huge timeouts run");
+            assert!(matches!(report.status, BlockStatus::Passed), "{mode:?}");
+        }
+    }
+
     #[test]
     fn docker_sandbox_prefers_cli_image_over_env() {
         const KEY: &str = "RUNME_DOCKER_IMAGE";
@@ -365,10 +853,15 @@ mod tests {
         unsafe {
             env::set_var(KEY, "env:image");
         }
-        let from_env = DockerSandbox::new(".", None, Vec::new());
+        let from_env = DockerSandbox::new(".", ContainerRuntime::default(), None, Vec::new());
         assert_eq!(from_env.image(), "env:image");
 
-        let from_cli = DockerSandbox::new(".", Some("cli:image".into()), vec!["--cpus=1".into()]);
+        let from_cli = DockerSandbox::new(
+            ".",
+            ContainerRuntime::default(),
+            Some("cli:image".into()),
+            vec!["--cpus=1".into()],
+        );
         assert_eq!(from_cli.image(), "cli:image");
         assert_eq!(from_cli.extra_args(), ["--cpus=1"]);
 
@@ -0,0 +1,355 @@
+//! Linux namespace sandbox: bubblewrap-style isolation without a container runtime.
+//!
+//! Every command starts in fresh, unprivileged user, mount, PID and network namespaces.
+//! Inside, the host root is visible but read-only, the document's workdir is bound
+//! writable at its usual path, `/tmp` is a private tmpfs, `/proc` only shows the
+//! sandboxed processes, and the network has nothing but loopback. The command runs as
+//! root of its user namespace, which maps to the invoking user on the host.
+//!
+//! Setup happens between `fork` and `exec` with raw syscalls only, so everything the
+//! child needs (paths, uid/gid maps) is prepared up front. Needs Linux 5.12 or newer with
+//! unprivileged user namespaces enabled.
+
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, anyhow};
+
+use super::sandbox::{CommandStatus, OutputSink, Sandbox, Session, spawn_with_streaming};
+use super::session::ShellSession;
+
+// Mount API constants from <linux/mount.h> and <linux/fcntl.h>, not all exported by libc.
+const AT_RECURSIVE: libc::c_uint = 0x8000;
+const MOUNT_ATTR_RDONLY: u64 = 0x1;
+const OPEN_TREE_CLONE: libc::c_uint = 0x1;
+const MOVE_MOUNT_F_EMPTY_PATH: libc::c_uint = 0x4;
+
+/// `struct mount_attr` for `mount_setattr(2)`.
+#[repr(C)]
+struct MountAttr {
+    attr_set: u64,
+    attr_clr: u64,
+    propagation: u64,
+    userns_fd: u64,
+}
+
+pub struct NsSandbox {
+    workdir: PathBuf,
+    setup: NsSetup,
+}
+
+impl NsSandbox {
+    pub fn new(workdir: impl Into<PathBuf>) -> Result<Self> {
+        let workdir = workdir.into();
+        let absolute = workdir
+            .canonicalize()
+            .with_context(|| format!("while resolving {}", workdir.display()))?;
+        let setup = NsSetup::new(&absolute)?;
+        Ok(Self {
+            workdir: absolute,
+            setup,
+        })
+    }
+
+    /// `command` with the namespace setup hooked in before `exec`.
+    fn isolate(&self, mut command: Command) -> Command {
+        let setup = self.setup.clone();
+        command.current_dir(&self.workdir);
+        // SAFETY: the hook only issues async-signal-safe syscalls on data prepared before fork.
+        unsafe {
+            command.pre_exec(move || setup.enter());
+        }
+        command
+    }
+}
+
+impl Sandbox for NsSandbox {
+    fn label(&self) -> &str {
+        "ns"
+    }
+
+    fn run(
+        &mut self,
+        argv: &[String],
+        timeout: Option<Duration>,
+        sink: &mut dyn OutputSink,
+    ) -> Result<CommandStatus> {
+        let (binary, rest) = argv
+            .split_first()
+            .ok_or_else(|| anyhow!("sandbox run requires at least one argument"))?;
+        let mut cmd = Command::new(binary);
+        cmd.args(rest);
+
+        let start = Instant::now();
+        let output = spawn_with_streaming(self.isolate(cmd), timeout, sink).with_context(|| {
+            format!("while invoking {binary} inside ns sandbox (are unprivileged user namespaces enabled?)")
+        })?;
+        Ok(output.with_duration(start.elapsed()))
+    }
+
+    fn workdir(&self) -> Option<PathBuf> {
+        Some(self.workdir.clone())
+    }
+
+    fn open_session(&mut self) -> Result<Box<dyn Session>> {
+        let mut cmd = Command::new("bash");
+        cmd.args(["--noprofile", "--norc"]);
+        Ok(Box::new(
+            ShellSession::spawn(self.isolate(cmd)).context("while starting ns session")?,
+        ))
+    }
+}
+
+/// Everything the forked child needs, as C strings ready for syscalls.
+#[derive(Clone)]
+struct NsSetup {
+    workdir: CString,
+    /// Ancestors of the workdir, outermost first, recreated when they sit under `/tmp`.
+    ancestors: Vec<CString>,
+    uid_map: CString,
+    gid_map: CString,
+}
+
+impl NsSetup {
+    fn new(workdir: &Path) -> Result<Self> {
+        let c_path = |path: &Path| {
+            CString::new(path.as_os_str().as_bytes())
+                .with_context(|| format!("{} contains a NUL byte", path.display()))
+        };
+        let mut ancestors = workdir
+            .ancestors()
+            .skip(1)
+            .filter(|dir| dir.parent().is_some())
+            .map(c_path)
+            .collect::<Result<Vec<_>>>()?;
+        ancestors.reverse();
+        // SAFETY: getuid/getgid cannot fail.
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        Ok(Self {
+            workdir: c_path(workdir)?,
+            ancestors,
+            uid_map: CString::new(format!("0 {uid} 1"))?,
+            gid_map: CString::new(format!("0 {gid} 1"))?,
+        })
+    }
+
+    /// Runs in the forked child: build the namespaces, then fork once more so the command
+    /// becomes PID 1 of its own PID namespace while this process waits and relays its exit.
+    fn enter(&self) -> io::Result<()> {
+        // SAFETY: plain syscalls on NUL-terminated strings owned by `self`.
+        unsafe {
+            check(libc::unshare(
+                libc::CLONE_NEWUSER | libc::CLONE_NEWNS | libc::CLONE_NEWNET,
+            ))?;
+            write_file(c"/proc/self/setgroups", c"deny")?;
+            write_file(c"/proc/self/uid_map", &self.uid_map)?;
+            write_file(c"/proc/self/gid_map", &self.gid_map)?;
+            check(libc::mount(
+                std::ptr::null(),
+                c"/".as_ptr(),
+                std::ptr::null(),
+                libc::MS_REC | libc::MS_PRIVATE,
+                std::ptr::null(),
+            ))?;
+
+            // Detach a writable copy of the workdir before the rest of the tree turns read-only.
+            let tree = check(libc::syscall(
+                libc::SYS_open_tree,
+                libc::AT_FDCWD,
+                self.workdir.as_ptr(),
+                OPEN_TREE_CLONE | AT_RECURSIVE | libc::O_CLOEXEC as libc::c_uint,
+            ) as libc::c_int)?;
+            let read_only = MountAttr {
+                attr_set: MOUNT_ATTR_RDONLY,
+                attr_clr: 0,
+                propagation: 0,
+                userns_fd: 0,
+            };
+            check(libc::syscall(
+                libc::SYS_mount_setattr,
+                libc::AT_FDCWD,
+                c"/".as_ptr(),
+                AT_RECURSIVE,
+                &read_only as *const MountAttr,
+                size_of::<MountAttr>(),
+            ) as libc::c_int)?;
+            check(libc::mount(
+                c"tmpfs".as_ptr(),
+                c"/tmp".as_ptr(),
+                c"tmpfs".as_ptr(),
+                libc::MS_NOSUID | libc::MS_NODEV,
+                c"mode=1777".as_ptr().cast(),
+            ))?;
+            // Workdirs under /tmp vanished behind the tmpfs; recreate the path to mount on.
+            for dir in self.ancestors.iter().chain([&self.workdir]) {
+                libc::mkdir(dir.as_ptr(), 0o755);
+            }
+            check(libc::syscall(
+                libc::SYS_move_mount,
+                tree,
+                c"".as_ptr(),
+                libc::AT_FDCWD,
+                self.workdir.as_ptr(),
+                MOVE_MOUNT_F_EMPTY_PATH,
+            ) as libc::c_int)?;
+            libc::close(tree);
+            // The inherited cwd still points below the read-only mount.
+            check(libc::chdir(self.workdir.as_ptr()))?;
+            loopback_up()?;
+
+            check(libc::unshare(libc::CLONE_NEWPID))?;
+            match check(libc::fork())? {
+                0 => {
+                    // Take the command down with runme even if the relay is killed first.
+                    libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL);
+                    check(libc::mount(
+                        c"proc".as_ptr(),
+                        c"/proc".as_ptr(),
+                        c"proc".as_ptr(),
+                        libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                        std::ptr::null(),
+                    ))?;
+                    Ok(())
+                }
+                child => relay_exit(child),
+            }
+        }
+    }
+}
+
+/// Wait for the sandboxed command and exit the same way, like a shell would report it.
+unsafe fn relay_exit(child: libc::pid_t) -> ! {
+    // SAFETY: only async-signal-safe calls; closing our copies of runme's pipes (including
+    // the exec-status pipe) leaves them to the command alone.
+    unsafe {
+        libc::syscall(libc::SYS_close_range, 3, libc::c_uint::MAX, 0);
+        let mut status = 0;
+        while libc::waitpid(child, &mut status, 0) < 0 {
+            if io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+                libc::_exit(127);
+            }
+        }
+        if libc::WIFSIGNALED(status) {
+            libc::_exit(128 + libc::WTERMSIG(status));
+        }
+        libc::_exit(libc::WEXITSTATUS(status))
+    }
+}
+
+/// Bring up `lo` so servers and clients inside the sandbox can still talk over localhost.
+unsafe fn loopback_up() -> io::Result<()> {
+    // SAFETY: `ifreq` is plain data; the ioctls only read and write it.
+    unsafe {
+        let socket = check(libc::socket(
+            libc::AF_INET,
+            libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+            0,
+        ))?;
+        let mut request: libc::ifreq = std::mem::zeroed();
+        for (slot, byte) in request.ifr_name.iter_mut().zip(b"lo") {
+            *slot = *byte as libc::c_char;
+        }
+        let result =
+            check(libc::ioctl(socket, libc::SIOCGIFFLAGS as _, &mut request)).and_then(|_| {
+                request.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+                check(libc::ioctl(socket, libc::SIOCSIFFLAGS as _, &request))
+            });
+        libc::close(socket);
+        result.map(drop)
+    }
+}
+
+unsafe fn write_file(path: &CStr, contents: &CStr) -> io::Result<()> {
+    // SAFETY: both pointers come from valid C strings.
+    unsafe {
+        let fd = check(libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC))?;
+        let bytes = contents.to_bytes();
+        let written = libc::write(fd, bytes.as_ptr().cast(), bytes.len());
+        libc::close(fd);
+        if written < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[derive(Default)]
+    struct Collect {
+        stdout: Vec<String>,
+        stderr: Vec<String>,
+    }
+
+    impl OutputSink for Collect {
+        fn on_stdout(&mut self, chunk: &str) {
+            self.stdout.push(chunk.to_string());
+        }
+
+        fn on_stderr(&mut self, chunk: &str) {
+            self.stderr.push(chunk.to_string());
+        }
+    }
+
+    fn sh(sandbox: &mut NsSandbox, script: &str) -> (CommandStatus, Collect) {
+        let mut sink = Collect::default();
+        let argv = ["sh".to_string(), "-c".to_string(), script.to_string()];
+        let status = sandbox
+            .run(&argv, None, &mut sink)
+            .expect("sandboxed command runs");
+        (status, sink)
+    }
+
+    #[test]
+    fn isolates_filesystem_processes_and_network() {
+        let id = std::process::id();
+        let dir = std::env::temp_dir().join(format!("runme-ns-{id}"));
+        fs::create_dir_all(&dir).unwrap();
+        let mut sandbox = NsSandbox::new(&dir).expect("sandbox");
+        // Only a kernel without unprivileged user namespaces may skip; it refuses the spawn.
+        if let Err(err) = sandbox.run(&["true".to_string()], None, &mut Collect::default()) {
+            eprintln!("skipping: unprivileged user namespaces are unavailable: {err:#}");
+            fs::remove_dir_all(&dir).unwrap();
+            return;
+        }
+        let host_file = PathBuf::from(format!("/tmp/runme-ns-{id}-host.txt"));
+        let inner_file = PathBuf::from(format!("/tmp/runme-ns-{id}-inner.txt"));
+        fs::write(&host_file, "host").unwrap();
+
+        let script = format!(
+            "echo kept > note.txt && echo pid=$$; \
+             test -e {host} || echo private-tmp; touch {inner}; \
+             touch /runme-ns-probe 2>/dev/null || echo read-only-root; \
+             grep -c : /proc/net/dev",
+            host = host_file.display(),
+            inner = inner_file.display(),
+        );
+        let (status, sink) = sh(&mut sandbox, &script);
+        fs::remove_file(&host_file).unwrap();
+        assert!(status.success, "{:?}", sink.stderr);
+        // The last line counts interfaces: only `lo` exists in the sandbox network.
+        assert_eq!(sink.stdout, ["pid=1", "private-tmp", "read-only-root", "1"]);
+        assert_eq!(fs::read_to_string(dir.join("note.txt")).unwrap(), "kept\n");
+        assert!(
+            !inner_file.exists(),
+            "files in the sandbox /tmp stay private"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,11 +1,18 @@
 use anyhow::Result;
 use std::io::{BufRead, BufReader};
-use std::process::{Command, Output, Stdio};
-use std::sync::mpsc::{self, Sender};
+use std::path::PathBuf;
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+/// How long to keep draining output after killing a timed-out process tree.
+const KILL_GRACE: Duration = Duration::from_secs(1);
 
 /// Low-level hook that sandboxes use to stream stdout/stderr data back to the runner.
+///
+/// Chunks are single lines without their line ending; blank lines arrive as empty chunks so
+/// expected-output updates can keep them.
 pub trait OutputSink {
     fn on_stdout(&mut self, chunk: &str);
     fn on_stderr(&mut self, chunk: &str);
@@ -17,6 +24,8 @@ pub struct CommandStatus {
     pub exit_code: Option<i32>,
     pub success: bool,
     pub duration: Duration,
+    /// True when the command was killed because its timeout expired.
+    pub timed_out: bool,
 }
 
 impl CommandStatus {
@@ -25,6 +34,7 @@ impl CommandStatus {
             exit_code: output.status.code(),
             success: output.status.success(),
             duration,
+            timed_out: false,
         }
     }
 
@@ -39,14 +49,50 @@ pub trait Sandbox {
     /// Short label surfaced in reports, e.g. `host` or `docker:ubuntu-22.04`.
     fn label(&self) -> &str;
     /// Run a parsed argv vector inside the sandbox environment and push stdout/stderr chunks
-    /// into the supplied sink as they arrive.
-    fn run(&mut self, argv: &[String], sink: &mut dyn OutputSink) -> Result<CommandStatus>;
+    /// into the supplied sink as they arrive. When `timeout` expires the whole process tree
+    /// is killed and the status reports `timed_out`.
+    fn run(
+        &mut self,
+        argv: &[String],
+        timeout: Option<Duration>,
+        sink: &mut dyn OutputSink,
+    ) -> Result<CommandStatus>;
+    /// Working directory as commands inside the sandbox see it; `runme:normalize workdir`
+    /// rewrites this path to `$WORKDIR`.
+    fn workdir(&self) -> Option<PathBuf> {
+        None
+    }
+    /// Start a long-lived shell whose state (cwd, env, functions) persists between snippets.
+    fn open_session(&mut self) -> Result<Box<dyn Session>> {
+        anyhow::bail!(
+            "{} sandbox does not support persistent sessions",
+            self.label()
+        )
+    }
+}
+
+/// A persistent shell opened by [`Sandbox::open_session`].
+pub trait Session {
+    /// Run shell source inside the session and report the exit status of its last command.
+    /// A timeout kills the session shell, so later snippets in the session fail.
+    fn run(
+        &mut self,
+        snippet: &str,
+        timeout: Option<Duration>,
+        sink: &mut dyn OutputSink,
+    ) -> Result<CommandStatus>;
 }
 
+/// Spawn `command`, forward its output to `sink`, and enforce `timeout` on the process tree.
 pub fn spawn_with_streaming(
     mut command: Command,
+    timeout: Option<Duration>,
     sink: &mut dyn OutputSink,
 ) -> Result<CommandStatus> {
+    if timeout.is_some() {
+        // A dedicated process group lets us kill grandchildren (e.g. `npm start`) on expiry.
+        own_process_group(&mut command);
+    }
     let mut child = command
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
@@ -63,7 +109,25 @@ pub fn spawn_with_streaming(
     }
     drop(tx);
 
-    for (kind, msg) in rx {
+    let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+    let mut timed_out = false;
+    loop {
+        let wait = match deadline {
+            _ if timed_out => KILL_GRACE,
+            Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+            None => Duration::MAX,
+        };
+        let (kind, msg) = match rx.recv_timeout(wait) {
+            Ok(message) => message,
+            Err(RecvTimeoutError::Disconnected) => break,
+            // Something outside the process group still holds the pipes; stop waiting.
+            Err(RecvTimeoutError::Timeout) if timed_out => break,
+            Err(RecvTimeoutError::Timeout) => {
+                kill_process_tree(&mut child);
+                timed_out = true;
+                continue;
+            }
+        };
         let chunk = msg?;
         match kind {
             StreamKind::Stdout => sink.on_stdout(&chunk),
@@ -71,15 +135,42 @@ pub fn spawn_with_streaming(
         }
     }
 
-    for handle in handles {
-        handle.join().expect("stream thread panicked")?;
+    if !timed_out {
+        for handle in handles {
+            handle.join().expect("stream thread panicked")?;
+        }
     }
 
     let output = child.wait_with_output()?;
-    Ok(CommandStatus::from_output(output, Duration::default()))
+    let mut status = CommandStatus::from_output(output, Duration::default());
+    status.timed_out = timed_out;
+    Ok(status)
 }
 
-fn spawn_reader(
+/// Put the spawned process in its own process group so the whole tree can be signalled.
+pub(super) fn own_process_group(command: &mut Command) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    #[cfg(not(unix))]
+    let _ = command;
+}
+
+/// Kill `child` and, on unix, every process in the group it leads.
+pub(super) fn kill_process_tree(child: &mut Child) {
+    #[cfg(unix)]
+    if let Ok(pid) = libc::pid_t::try_from(child.id()) {
+        // SAFETY: kill(2) has no memory-safety preconditions; a negative pid targets the group.
+        unsafe {
+            libc::kill(-pid, libc::SIGKILL);
+        }
+    }
+    let _ = child.kill();
+}
+
+pub(super) fn spawn_reader(
     kind: StreamKind,
     stream: impl std::io::Read + Send + 'static,
     sender: Sender<(StreamKind, Result<String>)>,
@@ -93,9 +184,6 @@ fn spawn_reader(
                 break;
             }
             let trimmed = line.trim_end_matches(&['\r', '\n'][..]).to_string();
-            if trimmed.is_empty() {
-                continue;
-            }
             sender
                 .send((kind, Ok(trimmed)))
                 .map_err(|_| anyhow::anyhow!("output channel closed"))?;
@@ -105,7 +193,7 @@ fn spawn_reader(
 }
 
 #[derive(Copy, Clone)]
-enum StreamKind {
+pub(super) enum StreamKind {
     Stdout,
     Stderr,
 }
@@ -0,0 +1,281 @@
+//! Whole-block execution through a shell interpreter (`runme:mode=script`).
+//!
+//! The block is passed verbatim to `bash`/`sh`/`zsh -c`, so pipes, redirects,
+//! conditionals and heredocs behave as they would in a terminal. For bash and
+//! zsh we enable `xtrace` with a marker prompt so stderr reveals which source
+//! line is running; the markers are stripped before anything reaches the report.
+
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+
+use super::sandbox::{CommandStatus, OutputSink, Sandbox, Session};
+use super::{CommandTranscript, ExecEvents, Execution, session};
+use crate::markdown::CodeBlock;
+
+/// Prefix emitted (after xtrace's nesting characters) in front of every traced command.
+const LINE_MARKER: &str = "runme:line:";
+/// Record separator; never appears in ordinary command output.
+const TRACE_CHAR: char = '\u{1e}';
+
+struct Interpreter {
+    program: &'static str,
+    flags: &'static [&'static str],
+    /// First script line enabling line tracing, if the shell can report `$LINENO` in `PS4`.
+    tracing: Option<&'static str>,
+}
+
+const BASH: Interpreter = Interpreter {
+    program: "bash",
+    flags: &["-euo", "pipefail"],
+    tracing: Some(r"PS4=$'\036runme:line:${LINENO} '; set -x"),
+};
+
+const ZSH: Interpreter = Interpreter {
+    program: "zsh",
+    flags: &["-euo", "pipefail"],
+    tracing: Some(r"PS4=$'\036runme:line:%i '; set -x"),
+};
+
+// dash and friends do not expand LINENO inside PS4, so failures are attributed to the block.
+const SH: Interpreter = Interpreter {
+    program: "sh",
+    flags: &["-eu"],
+    tracing: None,
+};
+
+fn interpreter_for(block: &CodeBlock) -> &'static Interpreter {
+    match block.language.as_deref().map(str::trim) {
+        Some(lang) if lang.eq_ignore_ascii_case("sh") => &SH,
+        Some(lang) if lang.eq_ignore_ascii_case("zsh") => &ZSH,
+        _ => &BASH,
+    }
+}
+
+/// Build the argv that runs `block` as one script; the preamble shifts line numbers by one.
+fn script_argv(interpreter: &Interpreter, block: &CodeBlock) -> Vec<String> {
+    let mut script = String::new();
+    if let Some(tracing) = interpreter.tracing {
+        script.push_str(tracing);
+        script.push('\n');
+    }
+    script.push_str(&block.content);
+
+    let mut argv = vec![interpreter.program.to_string()];
+    argv.extend(interpreter.flags.iter().map(|flag| flag.to_string()));
+    argv.push("-c".into());
+    argv.push(script);
+    argv
+}
+
+pub(super) fn execute_script(
+    block: &CodeBlock,
+    sandbox: &mut dyn Sandbox,
+    timeout: Option<Duration>,
+    events: &mut dyn ExecEvents,
+) -> Result<Execution> {
+    let interpreter = interpreter_for(block);
+    let argv = script_argv(interpreter, block);
+    let label = format!("{} {}", interpreter.program, interpreter.flags.join(" "));
+    let line_offset = usize::from(interpreter.tracing.is_some());
+
+    run_traced(block, events, timeout, line_offset, &label, |sink| {
+        sandbox
+            .run(&argv, timeout, sink)
+            .with_context(|| format!("while executing {} as a script", block.id))
+    })
+}
+
+/// Wrap `script` so a session stops at the first failing command like `bash -eo pipefail`.
+/// `set -e` would take the whole session shell down, so an `ERR` trap records the failure and
+/// every later top-level command is guarded by it. The commands stay in the session's own
+/// scope, so `declare` and other assignments carry over; the shell's options and `ERR` trap
+/// are restored afterwards. A failure inside a loop or function body stops the block only
+/// once that command returns.
+fn session_script(script: &str) -> String {
+    let mut wrapped = String::from(
+        "__runme_restore=\"$(set +o; trap -p ERR)\"\n\
+         __runme_status=0\n\
+         trap '__runme_status=$?' ERR\n\
+         set -o pipefail\n",
+    );
+    for (_, command) in session::group_commands(script) {
+        wrapped.push_str("if [ \"$__runme_status\" -eq 0 ]; then\n");
+        wrapped.push_str(&command);
+        wrapped.push_str("\nfi\n");
+    }
+    wrapped.push_str(
+        "trap - ERR; eval \"$__runme_restore\"\n\
+         unset __runme_restore\n\
+         (exit $__runme_status)",
+    );
+    wrapped
+}
+
+/// A session already is a shell, so the block runs in it with errexit and pipefail scoped to
+/// the block; failures are attributed to the block rather than a line.
+pub(super) fn execute_in_session(
+    block: &CodeBlock,
+    session: &mut dyn Session,
+    name: &str,
+    timeout: Option<Duration>,
+    events: &mut dyn ExecEvents,
+) -> Result<Execution> {
+    let label = format!("session {name}");
+    run_traced(block, events, timeout, 0, &label, |sink| {
+        session
+            .run(&session_script(&block.content), timeout, sink)
+            .with_context(|| format!("while executing {} in session {name}", block.id))
+    })
+}
+
+fn run_traced(
+    block: &CodeBlock,
+    events: &mut dyn ExecEvents,
+    timeout: Option<Duration>,
+    line_offset: usize,
+    fallback_label: &str,
+    run: impl FnOnce(&mut ScriptSink) -> Result<CommandStatus>,
+) -> Result<Execution> {
+    let mut sink = ScriptSink {
+        block,
+        lines: block.content.lines().collect(),
+        line_offset,
+        fallback_label,
+        current_line: None,
+        transcripts: Vec::new(),
+        open_line: None,
+        events,
+    };
+    let outcome = run(&mut sink)?;
+    // The last line observed is the one the script ended on, so it owns the exit status.
+    sink.ensure_line_started();
+    sink.finish_line(outcome.exit_code);
+
+    let mut execution = Execution {
+        executed_lines: block
+            .content
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.trim().starts_with('#'))
+            .count(),
+        duration: outcome.duration,
+        ..Execution::default()
+    };
+    if !outcome.success {
+        execution.fail(&outcome, timeout);
+        execution.failed_line = sink.current_line;
+    }
+    for (_, transcript) in sink.transcripts {
+        execution.record(transcript);
+    }
+    Ok(execution)
+}
+
+/// Routes interpreter output to per-line transcripts using the xtrace markers.
+///
+/// Markers share the stderr pipe with the script's own stderr, so stderr is attributed
+/// exactly. Stdout travels through a separate pipe and goes to the last marker seen when it
+/// arrives, which is best effort: output written right after a line starts may still be
+/// credited to the previous line.
+struct ScriptSink<'a> {
+    block: &'a CodeBlock,
+    lines: Vec<&'a str>,
+    line_offset: usize,
+    fallback_label: &'a str,
+    current_line: Option<usize>,
+    transcripts: Vec<(Option<usize>, CommandTranscript<'a>)>,
+    /// Line announced through `line_started` and when it began.
+    open_line: Option<(Option<usize>, Instant)>,
+    events: &'a mut dyn ExecEvents,
+}
+
+impl<'a> ScriptSink<'a> {
+    /// Parse an xtrace line into the 1-based block line it refers to.
+    fn parse_marker(&self, chunk: &str) -> Option<Option<usize>> {
+        let rest = chunk.trim_start_matches(TRACE_CHAR);
+        if rest.len() == chunk.len() {
+            return None;
+        }
+        let rest = rest.strip_prefix(LINE_MARKER)?;
+        let digits = rest.split(' ').next().unwrap_or_default();
+        // Lines inside the preamble (or unparsable numbers) are swallowed without moving.
+        Some(
+            digits
+                .parse::<usize>()
+                .ok()
+                .and_then(|line| line.checked_sub(self.line_offset))
+                .filter(|line| (1..=self.lines.len()).contains(line)),
+        )
+    }
+
+    fn command(&self, line: Option<usize>) -> &'a str {
+        match line {
+            Some(line) => self.lines[line - 1].trim(),
+            None => self.fallback_label,
+        }
+    }
+
+    /// Announce the current line to the observer, closing the previous one first.
+    fn ensure_line_started(&mut self) {
+        let current = self.current_line;
+        if self.open_line.is_some_and(|(line, _)| line == current) {
+            return;
+        }
+        // A script only moves on once the previous line is done, but its status is unknown.
+        self.finish_line(None);
+        self.events
+            .line_started(self.block, current, self.command(current));
+        self.open_line = Some((current, Instant::now()));
+    }
+
+    fn finish_line(&mut self, exit_code: Option<i32>) {
+        if let Some((line, started)) = self.open_line.take() {
+            self.events
+                .line_finished(self.block, line, exit_code, started.elapsed());
+        }
+    }
+
+    /// Transcript for the line currently executing, opening a new one when the line changes.
+    fn active(&mut self) -> &mut CommandTranscript<'a> {
+        self.ensure_line_started();
+        let current = self.current_line;
+        if self
+            .transcripts
+            .last()
+            .is_none_or(|(line, _)| *line != current)
+        {
+            let command = self.command(current);
+            self.transcripts
+                .push((current, CommandTranscript::new(command)));
+        }
+        &mut self
+            .transcripts
+            .last_mut()
+            .expect("transcript just ensured")
+            .1
+    }
+}
+
+impl OutputSink for ScriptSink<'_> {
+    fn on_stdout(&mut self, chunk: &str) {
+        self.active().append_stdout(chunk);
+        if !chunk.is_empty() {
+            self.events.stdout(self.block, self.current_line, chunk);
+        }
+    }
+
+    fn on_stderr(&mut self, chunk: &str) {
+        if let Some(marker) = self.parse_marker(chunk) {
+            if marker.is_some() {
+                self.current_line = marker;
+                self.ensure_line_started();
+            }
+            return;
+        }
+        if chunk.is_empty() {
+            return;
+        }
+        self.active().append_stderr(chunk);
+        self.events.stderr(self.block, self.current_line, chunk);
+    }
+}
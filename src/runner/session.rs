@@ -0,0 +1,448 @@
+//! Long-lived shells shared by several blocks of one document.
+//!
+//! A session keeps a single `bash` process alive and feeds it snippets over
+//! stdin, so `cd`, `export` and `source` behave like a reader following the
+//! tutorial in one terminal. Each snippet is followed by sentinel lines on
+//! stdout/stderr that carry the exit status and mark the end of its output.
+//! Lines are grouped into complete commands first, so heredocs, `\`
+//! continuations and multi-line `if`/`for`/function bodies never swallow the
+//! sentinels.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+
+use super::sandbox::{
+    CommandStatus, OutputSink, Sandbox, Session, StreamKind, kill_process_tree, own_process_group,
+    spawn_reader,
+};
+
+/// Sentinel prefix printed after every snippet; the record separator never shows up in docs.
+const DONE_MARKER: &str = "\u{1e}runme:done:";
+
+/// Sessions opened for the current document, keyed by `runme:session` name.
+#[derive(Default)]
+pub struct SessionPool {
+    sessions: HashMap<String, Box<dyn Session>>,
+}
+
+impl SessionPool {
+    /// Return the named session, spawning it through `sandbox` on first use.
+    pub fn get_or_open(
+        &mut self,
+        name: &str,
+        sandbox: &mut dyn Sandbox,
+    ) -> Result<&mut dyn Session> {
+        if !self.sessions.contains_key(name) {
+            let session = sandbox
+                .open_session()
+                .with_context(|| format!("while opening session '{name}'"))?;
+            self.sessions.insert(name.to_string(), session);
+        }
+        Ok(self
+            .sessions
+            .get_mut(name)
+            .expect("session inserted above")
+            .as_mut())
+    }
+}
+
+/// Split a block into complete shell commands, each tagged with its first line's 0-based index.
+///
+/// Blank and comment lines between commands are dropped; lines inside a command are kept as
+/// written so heredoc bodies reach the shell untouched. A command still open at the end of the
+/// block is sent as is, and the shell reports the syntax error.
+pub fn group_commands(content: &str) -> Vec<(usize, String)> {
+    let mut commands = Vec::new();
+    let mut pending: Option<(usize, String)> = None;
+    for (idx, raw_line) in content.lines().enumerate() {
+        match pending.as_mut() {
+            Some((_, command)) => {
+                command.push('\n');
+                command.push_str(raw_line);
+            }
+            None => {
+                let trimmed = raw_line.trim();
+                if trimmed.is_empty() || trimmed.starts_with('#') {
+                    continue;
+                }
+                pending = Some((idx, trimmed.to_string()));
+            }
+        }
+        if pending
+            .as_ref()
+            .is_some_and(|(_, command)| is_complete(command))
+        {
+            commands.extend(pending.take());
+        }
+    }
+    commands.extend(pending);
+    commands
+}
+
+/// Whether `bash` would parse `snippet` without waiting for more input.
+///
+/// This is a lightweight scan rather than a parser: it tracks quotes, heredocs, `\`
+/// continuations, parentheses and compound keywords in command position, which covers the
+/// shapes tutorials use. A misjudged command only changes how lines are batched.
+fn is_complete(snippet: &str) -> bool {
+    let mut scan = ShellScan::default();
+    for line in snippet.lines() {
+        scan.line(line);
+    }
+    let trailing_backslashes = snippet.chars().rev().take_while(|&c| c == '\\').count();
+    scan.is_closed() && trailing_backslashes % 2 == 0
+}
+
+/// Open constructs seen so far while scanning a snippet line by line.
+#[derive(Default)]
+struct ShellScan {
+    /// Quote character of a string left open at the end of a line.
+    quote: Option<char>,
+    /// Open `if`, `case`, loop and `{` bodies.
+    depth: usize,
+    /// Open `(` subshells and `$(` substitutions.
+    parens: usize,
+    /// Heredoc delimiters whose bodies start on the next line, with `<<-` tab stripping.
+    heredocs: VecDeque<(String, bool)>,
+    /// Heredoc whose body is being read.
+    body: Option<(String, bool)>,
+}
+
+impl ShellScan {
+    fn is_closed(&self) -> bool {
+        self.quote.is_none()
+            && self.depth == 0
+            && self.parens == 0
+            && self.heredocs.is_empty()
+            && self.body.is_none()
+    }
+
+    fn line(&mut self, line: &str) {
+        if let Some((delimiter, strip_tabs)) = &self.body {
+            let candidate = if *strip_tabs {
+                line.trim_start_matches('\t')
+            } else {
+                line
+            };
+            if candidate == delimiter {
+                self.body = self.heredocs.pop_front();
+            }
+            return;
+        }
+
+        let chars: Vec<char> = line.chars().collect();
+        let mut command_start = self.quote.is_none();
+        let mut word = String::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            i += 1;
+            match self.quote {
+                Some('\'') => {
+                    if c == '\'' {
+                        self.quote = None;
+                    }
+                    continue;
+                }
+                Some(quote) => {
+                    if c == '\\' {
+                        i += 1;
+                    } else if c == quote {
+                        self.quote = None;
+                    }
+                    continue;
+                }
+                None => {}
+            }
+            match c {
+                '\\' => {
+                    word.push(c);
+                    i += 1;
+                }
+                '\'' | '"' | '`' => {
+                    word.push(c);
+                    self.quote = Some(c);
+                }
+                '#' if word.is_empty() => break,
+                '<' if chars.get(i) == Some(&'<') => {
+                    if chars.get(i + 1) == Some(&'<') {
+                        // A here-string has no body.
+                        i += 2;
+                    } else {
+                        i = self.heredoc(&chars, i + 1);
+                    }
+                }
+                ' ' | '\t' | ';' | '&' | '|' | '(' | ')' => {
+                    self.word(&word, &mut command_start);
+                    word.clear();
+                    match c {
+                        ';' | '&' | '|' => command_start = true,
+                        '(' => {
+                            self.parens += 1;
+                            command_start = true;
+                        }
+                        ')' => self.parens = self.parens.saturating_sub(1),
+                        _ => {}
+                    }
+                }
+                _ => word.push(c),
+            }
+        }
+        self.word(&word, &mut command_start);
+        if self.quote.is_none() {
+            self.body = self.heredocs.pop_front();
+        }
+    }
+
+    /// Record the heredoc whose operator ends just before `i`; returns where scanning resumes.
+    fn heredoc(&mut self, chars: &[char], mut i: usize) -> usize {
+        let strip_tabs = chars.get(i) == Some(&'-');
+        if strip_tabs {
+            i += 1;
+        }
+        while chars.get(i).is_some_and(|c| c.is_whitespace()) {
+            i += 1;
+        }
+        let mut delimiter = String::new();
+        while let Some(&c) = chars.get(i) {
+            if c.is_whitespace() || ";&|<>()".contains(c) {
+                break;
+            }
+            if !"'\"\\".contains(c) {
+                delimiter.push(c);
+            }
+            i += 1;
+        }
+        if !delimiter.is_empty() {
+            self.heredocs.push_back((delimiter, strip_tabs));
+        }
+        i
+    }
+
+    /// Count compound keywords; they only open or close a body in command position.
+    fn word(&mut self, word: &str, command_start: &mut bool) {
+        if word.is_empty() {
+            return;
+        }
+        if !*command_start {
+            // `function name {` opens its body after a plain word.
+            if word == "{" {
+                self.depth += 1;
+                *command_start = true;
+            }
+            return;
+        }
+        match word {
+            "if" | "case" | "for" | "while" | "until" | "select" | "{" => self.depth += 1,
+            "fi" | "esac" | "done" | "}" => self.depth = self.depth.saturating_sub(1),
+            _ => {}
+        }
+        *command_start = matches!(
+            word,
+            "if" | "then" | "elif" | "else" | "while" | "until" | "do" | "{" | "!"
+        );
+    }
+}
+
+/// Session backed by a shell process reading commands from a pipe.
+pub struct ShellSession {
+    child: Child,
+    stdin: ChildStdin,
+    output: Receiver<(StreamKind, Result<String>)>,
+    exited: bool,
+    /// Extra command that stops work living outside the process tree, e.g. a container.
+    kill_command: Option<Command>,
+}
+
+impl ShellSession {
+    /// Spawn `command` (which must start a shell reading from stdin) with piped stdio.
+    pub fn spawn(mut command: Command) -> Result<Self> {
+        // Own process group so a timed-out snippet can be killed along with its children.
+        own_process_group(&mut command);
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("while spawning session shell")?;
+
+        let stdin = child.stdin.take().context("session stdin unavailable")?;
+        let (tx, rx) = mpsc::channel();
+        if let Some(stdout) = child.stdout.take() {
+            spawn_reader(StreamKind::Stdout, stdout, tx.clone());
+        }
+        if let Some(stderr) = child.stderr.take() {
+            spawn_reader(StreamKind::Stderr, stderr, tx);
+        }
+
+        Ok(Self {
+            child,
+            stdin,
+            output: rx,
+            exited: false,
+            kill_command: None,
+        })
+    }
+
+    /// Run `command` after killing the shell on timeout.
+    pub fn with_kill_command(mut self, command: Command) -> Self {
+        self.kill_command = Some(command);
+        self
+    }
+
+    fn shell_exited(&mut self, sink: &mut dyn OutputSink, start: Instant) -> Result<CommandStatus> {
+        self.exited = true;
+        let status = self.child.wait().context("while reaping session shell")?;
+        sink.on_stderr("runme: session shell exited; later blocks in this session will fail");
+        Ok(CommandStatus {
+            exit_code: status.code(),
+            success: false,
+            duration: start.elapsed(),
+            timed_out: false,
+        })
+    }
+
+    fn timed_out(&mut self, sink: &mut dyn OutputSink, start: Instant) -> CommandStatus {
+        self.exited = true;
+        kill_process_tree(&mut self.child);
+        let _ = self.child.wait();
+        if let Some(command) = self.kill_command.as_mut() {
+            let _ = command.status();
+        }
+        sink.on_stderr(
+            "runme: session shell killed after timeout; later blocks in this session will fail",
+        );
+        CommandStatus {
+            exit_code: None,
+            success: false,
+            duration: start.elapsed(),
+            timed_out: true,
+        }
+    }
+}
+
+impl Session for ShellSession {
+    fn run(
+        &mut self,
+        snippet: &str,
+        timeout: Option<Duration>,
+        sink: &mut dyn OutputSink,
+    ) -> Result<CommandStatus> {
+        let start = Instant::now();
+        let deadline = timeout.and_then(|timeout| start.checked_add(timeout));
+        if self.exited {
+            sink.on_stderr("runme: session shell is no longer running");
+            return Ok(CommandStatus {
+                exit_code: None,
+                success: false,
+                duration: Duration::default(),
+                timed_out: false,
+            });
+        }
+
+        // The brace group keeps state changes in the session shell while detaching stdin, so a
+        // stray `cat` cannot swallow the sentinels that follow.
+        let framed = format!(
+            "{{\n{snippet}\n}} </dev/null\nprintf '\\n{DONE_MARKER}%s\\n' \"$?\"\nprintf '\\n{DONE_MARKER}\\n' >&2\n"
+        );
+        if self.stdin.write_all(framed.as_bytes()).is_err() || self.stdin.flush().is_err() {
+            return self.shell_exited(sink, start);
+        }
+
+        let mut exit_code = None;
+        let mut stderr_done = false;
+        // A blank line is held back until the next one arrives: right before a sentinel it is
+        // just the newline the sentinel `printf` starts with.
+        let (mut stdout_blank, mut stderr_blank) = (false, false);
+        while exit_code.is_none() || !stderr_done {
+            let wait = deadline.map_or(Duration::MAX, |deadline| {
+                deadline.saturating_duration_since(Instant::now())
+            });
+            let (kind, chunk) = match self.output.recv_timeout(wait) {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) => return Ok(self.timed_out(sink, start)),
+                Err(RecvTimeoutError::Disconnected) => return self.shell_exited(sink, start),
+            };
+            let chunk = chunk?;
+            match (kind, chunk.strip_prefix(DONE_MARKER)) {
+                (StreamKind::Stdout, Some(code)) => {
+                    stdout_blank = false;
+                    exit_code = Some(code.trim().parse::<i32>().unwrap_or(-1));
+                }
+                (StreamKind::Stderr, Some(_)) => {
+                    stderr_blank = false;
+                    stderr_done = true;
+                }
+                (StreamKind::Stdout, None) => {
+                    if std::mem::replace(&mut stdout_blank, chunk.is_empty()) {
+                        sink.on_stdout("");
+                    }
+                    if !chunk.is_empty() {
+                        sink.on_stdout(&chunk);
+                    }
+                }
+                (StreamKind::Stderr, None) => {
+                    if std::mem::replace(&mut stderr_blank, chunk.is_empty()) {
+                        sink.on_stderr("");
+                    }
+                    if !chunk.is_empty() {
+                        sink.on_stderr(&chunk);
+                    }
+                }
+            }
+        }
+
+        let code = exit_code.expect("loop exits once the status arrives");
+        Ok(CommandStatus {
+            exit_code: Some(code),
+            success: code == 0,
+            duration: start.elapsed(),
+            timed_out: false,
+        })
+    }
+}
+
+impl Drop for ShellSession {
+    fn drop(&mut self) {
+        kill_process_tree(&mut self.child);
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_lines_into_complete_commands() {
+        let block = "# setup\n\
+                     while true\n\
+                     do break; done\n\
+                     echo 'if' \"done\" # fi\n\
+                     greet() {\n  echo hi\n}\n\
+                     case $1 in\n  a) echo (a) ;;\nesac\n\
+                     cat <<-'END' | tr a b\n\tend\n\tEND\n\
+                     echo \"open\nquote\"\n\
+                     echo $(\n  pwd\n)\n\
+                     cat <<< here";
+        let starts: Vec<usize> = group_commands(block).iter().map(|(idx, _)| *idx).collect();
+        assert_eq!(starts, vec![1, 3, 4, 7, 10, 13, 15, 18]);
+        assert_eq!(group_commands(block)[6].1, "echo $(\n  pwd\n)");
+    }
+
+    #[test]
+    fn unterminated_commands_run_to_the_end_of_the_block() {
+        assert_eq!(
+            group_commands("if true; then\n  echo never closed"),
+            vec![(0, "if true; then\n  echo never closed".to_string())]
+        );
+        assert!(!is_complete("cat <<EOF\nbody"));
+        assert!(!is_complete("echo one \\"));
+        assert!(is_complete("echo one \\\\"));
+    }
+}
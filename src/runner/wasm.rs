@@ -1,32 +1,397 @@
-use std::path::PathBuf;
+//! WASI sandbox: runs blocks against user-provided `.wasm` tools with Wasmtime.
+//!
+//! Each command name (`ls`, `bash`, ...) must be mapped to a WASI preview1
+//! module. Guests see the document's workdir as `.` and nothing else of the
+//! host: no environment, no sockets. Fuel and linear-memory limits bound every
+//! command, and timeouts interrupt the guest through epoch deadlines. Commands
+//! without a mapped tool fail like a shell's "command not found"; nothing ever
+//! falls back to running on the host.
 
-use anyhow::Result;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, anyhow};
+use wasmtime::{
+    Config, Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder, Trap, UpdateDeadline,
+};
+use wasmtime_wasi::pipe::MemoryOutputPipe;
+use wasmtime_wasi::preview1::{self, WasiP1Ctx};
+use wasmtime_wasi::{DirPerms, FilePerms, I32Exit, WasiCtxBuilder};
 
-use super::host::HostSandbox;
 use super::sandbox::{CommandStatus, OutputSink, Sandbox};
 
-/// Placeholder Wasm sandbox that executes commands on the host until
-/// Wasmtime-based runners are ready. This keeps the API stable while we build
-/// the actual runtime adapter.
+/// Exit status reported for commands with no mapped tool, as shells do.
+const EXIT_NOT_FOUND: i32 = 127;
+/// Captured output per stream; guests writing more see a write error.
+const OUTPUT_CAPACITY: usize = 16 * 1024 * 1024;
+
+/// Resource limits applied to every command.
+#[derive(Clone, Copy, Debug)]
+pub struct WasmLimits {
+    /// Fuel units (roughly Wasm instructions) a command may consume.
+    pub fuel: u64,
+    /// Linear memory a command may grow to, in bytes.
+    pub memory_bytes: usize,
+}
+
+impl Default for WasmLimits {
+    fn default() -> Self {
+        Self {
+            fuel: 10_000_000_000,
+            memory_bytes: 512 * 1024 * 1024,
+        }
+    }
+}
+
+struct GuestState {
+    wasi: WasiP1Ctx,
+    limits: StoreLimits,
+}
+
 pub struct WasmSandbox {
-    host_fallback: HostSandbox,
+    workdir: PathBuf,
+    /// Command name to `.wasm` file.
+    tools: HashMap<String, PathBuf>,
+    limits: WasmLimits,
+    engine: Engine,
+    linker: Linker<GuestState>,
+    /// Tools compiled so far, so each module is compiled once per document.
+    modules: HashMap<String, Module>,
 }
 
 impl WasmSandbox {
-    pub fn new(workdir: impl Into<PathBuf>) -> Self {
-        Self {
-            host_fallback: HostSandbox::new(workdir),
+    pub fn new(
+        workdir: impl Into<PathBuf>,
+        tools: HashMap<String, PathBuf>,
+        limits: WasmLimits,
+    ) -> Result<Self> {
+        let mut config = Config::new();
+        config.consume_fuel(true).epoch_interruption(true);
+        let engine = Engine::new(&config).context("while configuring the Wasm engine")?;
+        let mut linker = Linker::new(&engine);
+        preview1::add_to_linker_sync(&mut linker, |state: &mut GuestState| &mut state.wasi)?;
+        Ok(Self {
+            workdir: workdir.into(),
+            tools,
+            limits,
+            engine,
+            linker,
+            modules: HashMap::new(),
+        })
+    }
+
+    fn module(&mut self, name: &str, path: &Path) -> Result<Module> {
+        if let Some(module) = self.modules.get(name) {
+            return Ok(module.clone());
+        }
+        let module = Module::from_file(&self.engine, path)
+            .with_context(|| format!("while compiling Wasm tool {name} from {}", path.display()))?;
+        self.modules.insert(name.to_string(), module.clone());
+        Ok(module)
+    }
+
+    /// Instantiate `module` with a fresh WASI context and run its `_start` export.
+    fn execute(
+        &self,
+        module: &Module,
+        argv: &[String],
+        timeout: Option<Duration>,
+    ) -> Result<Outcome> {
+        let stdout = MemoryOutputPipe::new(OUTPUT_CAPACITY);
+        let stderr = MemoryOutputPipe::new(OUTPUT_CAPACITY);
+        let wasi = WasiCtxBuilder::new()
+            .args(argv)
+            .stdout(stdout.clone())
+            .stderr(stderr.clone())
+            .allow_tcp(false)
+            .allow_udp(false)
+            .allow_ip_name_lookup(false)
+            .preopened_dir(&self.workdir, ".", DirPerms::all(), FilePerms::all())
+            .with_context(|| format!("while preopening {}", self.workdir.display()))?
+            .build_p1();
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(self.limits.memory_bytes)
+            .build();
+        let mut store = Store::new(&self.engine, GuestState { wasi, limits });
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(self.limits.fuel)?;
+        // The engine's epoch is shared by every command, so a bump only interrupts the guest
+        // whose own watchdog expired; any other store just waits for the next tick.
+        let expired = Arc::new(AtomicBool::new(false));
+        store.set_epoch_deadline(1);
+        let interrupt = Arc::clone(&expired);
+        store.epoch_deadline_callback(move |_| {
+            if interrupt.load(Ordering::SeqCst) {
+                Err(Trap::Interrupt.into())
+            } else {
+                Ok(UpdateDeadline::Continue(1))
+            }
+        });
+
+        let (done, watchdog) = match timeout {
+            Some(timeout) => {
+                let (done, finished) = mpsc::channel::<()>();
+                let engine = self.engine.clone();
+                let expired = Arc::clone(&expired);
+                let watchdog = thread::spawn(move || {
+                    if let Err(mpsc::RecvTimeoutError::Timeout) = finished.recv_timeout(timeout) {
+                        expired.store(true, Ordering::SeqCst);
+                        engine.increment_epoch();
+                    }
+                });
+                (Some(done), Some(watchdog))
+            }
+            None => (None, None),
+        };
+
+        let result = self
+            .linker
+            .instantiate(&mut store, module)
+            .and_then(|instance| instance.get_typed_func::<(), ()>(&mut store, "_start"))
+            .and_then(|start| start.call(&mut store, ()));
+        drop(done);
+        if let Some(watchdog) = watchdog {
+            let _ = watchdog.join();
         }
+
+        let mut outcome = Outcome {
+            exit_code: Some(0),
+            timed_out: false,
+            stdout: String::from_utf8_lossy(&stdout.contents()).into_owned(),
+            stderr: String::from_utf8_lossy(&stderr.contents()).into_owned(),
+        };
+        if let Err(err) = result {
+            if let Some(exit) = err.downcast_ref::<I32Exit>() {
+                outcome.exit_code = Some(exit.0);
+            } else {
+                outcome.exit_code = None;
+                let message = match err.downcast_ref::<Trap>() {
+                    Some(Trap::Interrupt) => {
+                        outcome.timed_out = true;
+                        "interrupted after its timeout".to_string()
+                    }
+                    Some(Trap::OutOfFuel) => {
+                        format!("ran out of fuel ({} units)", self.limits.fuel)
+                    }
+                    _ => format!("trapped: {err:#}"),
+                };
+                outcome
+                    .stderr
+                    .push_str(&format!("runme: wasm tool {} {message}\n", argv[0]));
+            }
+        }
+        Ok(outcome)
     }
 }
 
+struct Outcome {
+    exit_code: Option<i32>,
+    timed_out: bool,
+    stdout: String,
+    stderr: String,
+}
+
 impl Sandbox for WasmSandbox {
     fn label(&self) -> &str {
-        "wasm(host-fallback)"
+        "wasm"
     }
 
-    fn run(&mut self, argv: &[String], sink: &mut dyn OutputSink) -> Result<CommandStatus> {
-        // TODO: spin up Wasmtime modules to execute supported languages.
-        self.host_fallback.run(argv, sink)
+    fn run(
+        &mut self,
+        argv: &[String],
+        timeout: Option<Duration>,
+        sink: &mut dyn OutputSink,
+    ) -> Result<CommandStatus> {
+        let start = Instant::now();
+        let name = argv
+            .first()
+            .ok_or_else(|| anyhow!("sandbox run requires at least one argument"))?;
+        let Some(path) = self.tools.get(name).cloned() else {
+            sink.on_stderr(&format!(
+                "runme: `{name}` is not mapped to a Wasm tool; add --wasm-tool {name}=path/to/{name}.wasm"
+            ));
+            return Ok(CommandStatus {
+                exit_code: Some(EXIT_NOT_FOUND),
+                success: false,
+                duration: start.elapsed(),
+                timed_out: false,
+            });
+        };
+        let module = self.module(name, &path)?;
+        let outcome = self
+            .execute(&module, argv, timeout)
+            .with_context(|| format!("while invoking {name} inside wasm sandbox"))?;
+
+        // Guest output is buffered by the runtime, so it reaches the sink once the command ends.
+        for line in outcome.stdout.lines() {
+            sink.on_stdout(line.trim_end_matches('\r'));
+        }
+        for line in outcome.stderr.lines() {
+            sink.on_stderr(line.trim_end_matches('\r'));
+        }
+        Ok(CommandStatus {
+            exit_code: outcome.exit_code,
+            success: outcome.exit_code == Some(0),
+            duration: start.elapsed(),
+            timed_out: outcome.timed_out,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[derive(Default)]
+    struct Collect {
+        stdout: Vec<String>,
+        stderr: Vec<String>,
+    }
+
+    impl OutputSink for Collect {
+        fn on_stdout(&mut self, chunk: &str) {
+            self.stdout.push(chunk.to_string());
+        }
+
+        fn on_stderr(&mut self, chunk: &str) {
+            self.stderr.push(chunk.to_string());
+        }
+    }
+
+    /// `hello` writes a greeting and exits 3; `ok` returns at once; `spin` loops forever.
+    const TOOLS: &[(&str, &str)] = &[
+        (
+            "hello",
+            r#"(module
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "proc_exit" (func $exit (param i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 16) "hello from wasi\n")
+                (func (export "_start")
+                    (i32.store (i32.const 0) (i32.const 16))
+                    (i32.store (i32.const 4) (i32.const 16))
+                    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+                    (call $exit (i32.const 3))))"#,
+        ),
+        (
+            "ok",
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "_start")))"#,
+        ),
+        (
+            "spin",
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "_start") (loop $forever (br $forever))))"#,
+        ),
+    ];
+
+    /// Sandbox over the test tools in a temp dir of its own; `name` keeps parallel tests apart.
+    fn tool_sandbox(name: &str, limits: WasmLimits) -> (WasmSandbox, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("runme-wasm-{}-{name}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut tools = HashMap::new();
+        for (name, source) in TOOLS {
+            let path = dir.join(format!("{name}.wasm"));
+            fs::write(&path, wat::parse_str(source).expect("valid wat")).unwrap();
+            tools.insert(name.to_string(), path);
+        }
+        let sandbox = WasmSandbox::new(&dir, tools, limits).expect("engine");
+        (sandbox, dir)
+    }
+
+    #[test]
+    fn runs_mapped_tools_and_refuses_unmapped_commands() {
+        let (mut sandbox, dir) = tool_sandbox("mapped", WasmLimits::default());
+        let mut sink = Collect::default();
+        let status = sandbox
+            .run(&["hello".into()], None, &mut sink)
+            .expect("hello runs");
+        assert_eq!(status.exit_code, Some(3));
+        assert_eq!(sink.stdout, ["hello from wasi"]);
+
+        let mut sink = Collect::default();
+        let status = sandbox
+            .run(&["ls".into(), "-l".into()], None, &mut sink)
+            .expect("unmapped command is reported");
+        assert_eq!(status.exit_code, Some(EXIT_NOT_FOUND));
+        assert!(sink.stderr[0].contains("`ls` is not mapped to a Wasm tool"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn fuel_and_timeouts_stop_runaway_guests() {
+        let (mut sandbox, dir) = tool_sandbox(
+            "fuel",
+            WasmLimits {
+                fuel: 100_000,
+                ..WasmLimits::default()
+            },
+        );
+        let mut sink = Collect::default();
+        let status = sandbox
+            .run(&["spin".into()], None, &mut sink)
+            .expect("spin runs");
+        assert_eq!(status.exit_code, None);
+        assert!(!status.timed_out);
+        assert_eq!(
+            sink.stderr,
+            ["runme: wasm tool spin ran out of fuel (100000 units)"]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        let (mut sandbox, dir) = tool_sandbox(
+            "timeout",
+            WasmLimits {
+                fuel: u64::MAX,
+                ..WasmLimits::default()
+            },
+        );
+        let status = sandbox
+            .run(
+                &["spin".into()],
+                Some(Duration::from_millis(200)),
+                &mut Collect::default(),
+            )
+            .expect("spin runs");
+        assert!(status.timed_out);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn commands_after_a_timeout_run_normally() {
+        let (mut sandbox, dir) = tool_sandbox(
+            "after-timeout",
+            WasmLimits {
+                fuel: u64::MAX,
+                ..WasmLimits::default()
+            },
+        );
+        let status = sandbox
+            .run(
+                &["spin".into()],
+                Some(Duration::from_millis(100)),
+                &mut Collect::default(),
+            )
+            .expect("spin runs");
+        assert!(status.timed_out);
+
+        for timeout in [None, Some(Duration::from_secs(60))] {
+            let mut sink = Collect::default();
+            let status = sandbox
+                .run(&["ok".into()], timeout, &mut sink)
+                .expect("ok runs");
+            assert_eq!(status.exit_code, Some(0), "stderr: {:?}", sink.stderr);
+            assert!(!status.timed_out);
+        }
+        fs::remove_dir_all(dir).unwrap();
     }
 }
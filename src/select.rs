@@ -0,0 +1,393 @@
+//! Pick which blocks `list` and `run` operate on.
+//!
+//! Each kind of filter (`--file`, `--block`, `--tag`, `--heading`, `--lang`,
+//! `--name`) accepts several values, and a block passes a kind when it matches any of
+//! them. A block is selected when it passes every kind that was given and
+//! matches none of the `--exclude-*` filters.
+//!
+//! Selected blocks then pull in their `runme:needs` prerequisites, which run
+//! first and once each, and are wrapped by their document's `runme:setup` and
+//! `runme:teardown` blocks.
+
+use std::path::Path;
+
+use anyhow::{Result, bail};
+use clap::Args;
+use globset::{Glob, GlobBuilder, GlobMatcher};
+
+use crate::markdown::{CodeBlock, Phase};
+
+#[derive(Args, Debug, Clone, Default)]
+pub struct BlockFilter {
+    /// Select documents whose path (as discovered, e.g. `docs/setup.md`) matches a glob such
+    /// as `docs/**/*.md`; repeatable.
+    #[arg(long, value_name = "GLOB", value_parser = parse_path_glob)]
+    pub file: Vec<GlobMatcher>,
+    /// Block identifier (e.g. block-002) or custom name (`runme:name ...`) to select;
+    /// repeatable. A document contributes its first block per key.
+    #[arg(long, value_name = "ID|NAME")]
+    pub block: Vec<String>,
+    /// Select blocks tagged with `runme:tags=...`; repeatable.
+    #[arg(long, value_name = "TAG")]
+    pub tag: Vec<String>,
+    /// Select blocks under a heading with this title at any level; repeatable.
+    #[arg(long, value_name = "TITLE")]
+    pub heading: Vec<String>,
+    /// Select blocks with this info-string language, e.g. `bash`; repeatable.
+    #[arg(long, value_name = "LANG")]
+    pub lang: Vec<String>,
+    /// Select blocks whose `runme:name` matches a glob such as `deploy-*`; repeatable.
+    #[arg(long, value_name = "GLOB", value_parser = parse_glob)]
+    pub name: Vec<GlobMatcher>,
+    /// Drop documents whose path matches this glob; repeatable.
+    #[arg(long, value_name = "GLOB", value_parser = parse_path_glob)]
+    pub exclude_file: Vec<GlobMatcher>,
+    /// Drop blocks with this id or name; repeatable.
+    #[arg(long, value_name = "ID|NAME")]
+    pub exclude_block: Vec<String>,
+    /// Drop blocks carrying this tag; repeatable.
+    #[arg(long, value_name = "TAG")]
+    pub exclude_tag: Vec<String>,
+    /// Drop blocks under a heading with this title; repeatable.
+    #[arg(long, value_name = "TITLE")]
+    pub exclude_heading: Vec<String>,
+    /// Drop blocks with this language; repeatable.
+    #[arg(long, value_name = "LANG")]
+    pub exclude_lang: Vec<String>,
+    /// Drop blocks whose name matches this glob; repeatable.
+    #[arg(long, value_name = "GLOB", value_parser = parse_glob)]
+    pub exclude_name: Vec<GlobMatcher>,
+}
+
+impl BlockFilter {
+    /// True when no filter was given and every block is selected.
+    pub fn is_empty(&self) -> bool {
+        self.file.is_empty()
+            && self.block.is_empty()
+            && self.tag.is_empty()
+            && self.heading.is_empty()
+            && self.lang.is_empty()
+            && self.name.is_empty()
+            && self.exclude_file.is_empty()
+            && self.exclude_block.is_empty()
+            && self.exclude_tag.is_empty()
+            && self.exclude_heading.is_empty()
+            && self.exclude_lang.is_empty()
+            && self.exclude_name.is_empty()
+    }
+
+    /// Blocks of the document at `path` that pass the filters, in document order.
+    pub fn select<'a>(&self, path: &Path, blocks: &'a [CodeBlock]) -> Vec<&'a CodeBlock> {
+        let file_selected = (self.file.is_empty()
+            || self.file.iter().any(|glob| glob.is_match(path)))
+            && !self.exclude_file.iter().any(|glob| glob.is_match(path));
+        if !file_selected {
+            return Vec::new();
+        }
+        let keyed: Vec<&str> = self
+            .block
+            .iter()
+            .filter_map(|key| find(blocks, key))
+            .map(|block| block.id.as_str())
+            .collect();
+        blocks
+            .iter()
+            .filter(|block| self.block.is_empty() || keyed.contains(&block.id.as_str()))
+            .filter(|block| self.includes(block) && !self.excludes(block))
+            .collect()
+    }
+
+    /// `--block` keys that name no block in any of `blocks`.
+    pub fn unknown_keys<'a>(&'a self, blocks: &[&CodeBlock]) -> Vec<&'a str> {
+        self.block
+            .iter()
+            .filter(|key| !blocks.iter().any(|block| has_key(block, key)))
+            .map(String::as_str)
+            .collect()
+    }
+
+    fn includes(&self, block: &CodeBlock) -> bool {
+        (self.tag.is_empty() || self.tag.iter().any(|tag| has_tag(block, tag)))
+            && (self.heading.is_empty()
+                || self.heading.iter().any(|title| has_heading(block, title)))
+            && (self.lang.is_empty() || self.lang.iter().any(|lang| has_lang(block, lang)))
+            && (self.name.is_empty() || self.name.iter().any(|glob| name_matches(block, glob)))
+    }
+
+    fn excludes(&self, block: &CodeBlock) -> bool {
+        self.exclude_block.iter().any(|key| has_key(block, key))
+            || self.exclude_tag.iter().any(|tag| has_tag(block, tag))
+            || self
+                .exclude_heading
+                .iter()
+                .any(|title| has_heading(block, title))
+            || self.exclude_lang.iter().any(|lang| has_lang(block, lang))
+            || self
+                .exclude_name
+                .iter()
+                .any(|glob| name_matches(block, glob))
+    }
+}
+
+/// First block of a document whose id or name is `key`, as `--block` and `runme:needs` resolve it.
+pub fn find<'a>(blocks: &'a [CodeBlock], key: &str) -> Option<&'a CodeBlock> {
+    blocks.iter().find(|block| has_key(block, key))
+}
+
+/// Everything to run for one document's `selected` blocks, in order: setup blocks, the
+/// selection with its prerequisites, then teardown blocks. Documents with nothing selected
+/// run nothing, not even their setup.
+pub fn plan<'a>(blocks: &'a [CodeBlock], selected: &[&'a CodeBlock]) -> Result<Vec<&'a CodeBlock>> {
+    let main: Vec<&CodeBlock> = selected
+        .iter()
+        .copied()
+        .filter(|block| block.phase == Phase::Main)
+        .collect();
+    if main.is_empty() {
+        return Ok(Vec::new());
+    }
+    let in_phase = |phase: Phase| blocks.iter().filter(move |block| block.phase == phase);
+    let wanted: Vec<&CodeBlock> = in_phase(Phase::Setup)
+        .chain(main)
+        .chain(in_phase(Phase::Teardown))
+        .collect();
+    with_prerequisites(blocks, &wanted)
+}
+
+/// Order `selected` for execution: every block comes after the blocks it needs,
+/// prerequisites are added when missing, and no block appears twice.
+pub fn with_prerequisites<'a>(
+    blocks: &'a [CodeBlock],
+    selected: &[&'a CodeBlock],
+) -> Result<Vec<&'a CodeBlock>> {
+    let mut ordered = Vec::new();
+    let mut path = Vec::new();
+    for block in selected {
+        visit(blocks, block, &mut path, &mut ordered)?;
+    }
+    Ok(ordered)
+}
+
+/// Depth-first walk over `runme:needs`; `path` holds the chain being resolved to spot cycles.
+fn visit<'a>(
+    blocks: &'a [CodeBlock],
+    block: &'a CodeBlock,
+    path: &mut Vec<&'a CodeBlock>,
+    ordered: &mut Vec<&'a CodeBlock>,
+) -> Result<()> {
+    if ordered.iter().any(|done| done.id == block.id) {
+        return Ok(());
+    }
+    if let Some(start) = path.iter().position(|seen| seen.id == block.id) {
+        let cycle: Vec<String> = path[start..]
+            .iter()
+            .chain([&block])
+            .map(|block| label(block))
+            .collect();
+        bail!("runme:needs cycle: {}", cycle.join(" -> "));
+    }
+    path.push(block);
+    for key in &block.needs {
+        let Some(need) = find(blocks, key) else {
+            bail!(
+                "{} needs `{key}`, but no block has that id or runme:name",
+                label(block)
+            );
+        };
+        visit(blocks, need, path, ordered)?;
+    }
+    path.pop();
+    ordered.push(block);
+    Ok(())
+}
+
+fn label(block: &CodeBlock) -> String {
+    match &block.name {
+        Some(name) => format!("{} ({name})", block.id),
+        None => block.id.clone(),
+    }
+}
+
+/// Path globs follow target discovery: `*` stays within one directory, `**` spans several.
+fn parse_path_glob(raw: &str) -> Result<GlobMatcher, String> {
+    GlobBuilder::new(raw)
+        .literal_separator(true)
+        .build()
+        .map(|glob| glob.compile_matcher())
+        .map_err(|err| err.to_string())
+}
+
+fn parse_glob(raw: &str) -> Result<GlobMatcher, String> {
+    Glob::new(raw)
+        .map(|glob| glob.compile_matcher())
+        .map_err(|err| err.to_string())
+}
+
+fn has_key(block: &CodeBlock, key: &str) -> bool {
+    block.id == key || block.name.as_deref() == Some(key)
+}
+
+fn has_tag(block: &CodeBlock, tag: &str) -> bool {
+    block.tags.iter().any(|own| own == tag)
+}
+
+fn has_heading(block: &CodeBlock, title: &str) -> bool {
+    block
+        .headings
+        .iter()
+        .any(|heading| heading.eq_ignore_ascii_case(title.trim()))
+}
+
+fn has_lang(block: &CodeBlock, lang: &str) -> bool {
+    block
+        .language
+        .as_deref()
+        .is_some_and(|own| own.eq_ignore_ascii_case(lang))
+}
+
+fn name_matches(block: &CodeBlock, glob: &GlobMatcher) -> bool {
+    block
+        .name
+        .as_deref()
+        .is_some_and(|name| glob.is_match(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markdown::extract_blocks;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        filter: BlockFilter,
+    }
+
+    fn selected_ids(doc: &str, args: &[&str]) -> Vec<String> {
+        selected_ids_in("README.md", doc, args)
+    }
+
+    fn selected_ids_in(path: &str, doc: &str, args: &[&str]) -> Vec<String> {
+        let blocks = extract_blocks(doc).expect("parse");
+        let cli = Cli::try_parse_from(std::iter::once("runme").chain(args.iter().copied()))
+            .expect("parse filters");
+        cli.filter
+            .select(Path::new(path), &blocks)
+            .into_iter()
+            .map(|block| block.id.clone())
+            .collect()
+    }
+
+    #[test]
+    fn combines_filters_with_and_across_kinds_and_or_within() {
+        let doc = r#"
+# Install
+
+```bash runme:name=deploy-staging runme:tags=smoke,slow
+echo 1
+```
+
+```python runme:name=deploy-prod runme:tags=slow
+print(2)
+```
+
+## Usage
+
+```bash runme:name=usage runme:tags=smoke
+echo 3
+```
+
+```sh
+echo 4
+```
+"#;
+        assert_eq!(selected_ids(doc, &[]).len(), 4);
+        assert_eq!(
+            selected_ids(doc, &["--tag", "smoke"]),
+            ["block-001", "block-003"]
+        );
+        assert_eq!(
+            selected_ids(
+                doc,
+                &["--tag", "smoke", "--lang", "python", "--lang", "BASH"]
+            ),
+            ["block-001", "block-003"]
+        );
+        assert_eq!(
+            selected_ids(doc, &["--heading", "usage", "--exclude-tag", "smoke"]),
+            ["block-004"]
+        );
+        assert_eq!(
+            selected_ids(doc, &["--name", "deploy-*", "--exclude-name", "*-prod"]),
+            ["block-001"]
+        );
+        assert_eq!(
+            selected_ids(doc, &["--block", "usage", "--block", "block-002"]),
+            ["block-002", "block-003"]
+        );
+        assert_eq!(
+            selected_ids(
+                doc,
+                &["--exclude-block", "block-001", "--exclude-lang", "sh"]
+            ),
+            ["block-002", "block-003"]
+        );
+    }
+
+    #[test]
+    fn file_globs_match_document_paths() {
+        let doc = "```bash\necho 1\n```\n";
+        let args = ["--file", "docs/**/*.md", "--exclude-file", "docs/drafts/*"];
+        assert_eq!(selected_ids_in("docs/guide.md", doc, &args), ["block-001"]);
+        assert_eq!(
+            selected_ids_in("docs/cli/run.md", doc, &args),
+            ["block-001"]
+        );
+        assert!(selected_ids_in("docs/drafts/wip.md", doc, &args).is_empty());
+        assert!(selected_ids_in("README.md", doc, &args).is_empty());
+        assert!(selected_ids_in("docs/cli/run.md", doc, &["--file", "docs/*.md"]).is_empty());
+    }
+
+    #[test]
+    fn prerequisites_run_first_once_and_cycles_are_rejected() {
+        let doc = r#"
+```bash runme:name=install-deps
+npm ci
+```
+
+```bash runme:name=build runme:needs=install-deps
+npm run build
+```
+
+```bash runme:name=start-server runme:needs=build,install-deps
+npm start
+```
+"#;
+        let blocks = extract_blocks(doc).expect("parse");
+        let selected = [&blocks[2], &blocks[1]];
+        let ordered: Vec<&str> = with_prerequisites(&blocks, &selected)
+            .expect("resolve")
+            .into_iter()
+            .map(|block| block.id.as_str())
+            .collect();
+        assert_eq!(ordered, ["block-001", "block-002", "block-003"]);
+
+        let cyclic = extract_blocks(
+            "```bash runme:name=a runme:needs=b\nls\n```\n\n```bash runme:name=b runme:needs=a\nls\n```\n",
+        )
+        .expect("parse");
+        let err = with_prerequisites(&cyclic, &[&cyclic[0]]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "runme:needs cycle: block-001 (a) -> block-002 (b) -> block-001 (a)"
+        );
+
+        let unknown = extract_blocks("```bash runme:needs=setup\nls\n```\n").expect("parse");
+        let err = with_prerequisites(&unknown, &[&unknown[0]]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "block-001 needs `setup`, but no block has that id or runme:name"
+        );
+    }
+}